@@ -102,6 +102,9 @@ std::mem::drop(sender);
 th.join().unwrap_err();  // RecvError::Orphaned
 ```
 
+If you want the pool's membership to change over time, use `Pool`, which keeps track of the
+receivers it has created and lets you remove them again.
+
 ## Design
 
 Each receiver has a "slot" which is either empty, blocked, or contains a pointer to some work.
@@ -143,190 +146,6277 @@ burst_chan                   | 5875 | 7344 | 12265 | 30397   | 47118   | 15984
 spmc                         | 4170 | 7050 | 14561 | 34644   | 59763   | 18003  | 12511
 */
 
-extern crate nix;
 extern crate byteorder;
+#[cfg(feature = "hdr")]
+pub extern crate hdrhistogram;
+extern crate libc;
 
 use byteorder::*;
-use nix::sys::eventfd::*;
-use nix::unistd::*;
+use std::any::{type_name, Any};
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::collections::{BTreeSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::hint;
+use std::io;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ops::{Deref, DerefMut, Range};
 use std::os::unix::io::RawFd;
+use std::panic;
+use std::pin::Pin;
 use std::ptr;
 use std::thread;
 use std::sync::atomic::*;
-use std::sync::Arc;
-use nix::poll::*;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Chooses which receiver to send to. See `Sender::set_selector`.
+type Selector = Box<dyn FnMut(&[WorkerState]) -> Option<usize> + Send>;
 
 pub struct Sender<T> {
-    eventfd: RawFd,
-    workers: Vec<Arc<Worker<T>>>,
+    eventfd: Arc<EventFd>,
+    registry: Arc<Registry<T>>,
+    workers: Vec<Arc<Worker<T>>>,   // our copy of the registry's list
+    version: usize,                 // the registry version `workers` was copied from
     next_worker: usize,
+    last_worker: Option<usize>,   // the worker which accepted the previous payload
     workers_to_unblock: i64,
-    eventfd_buf: [u8; 8],
+    counters: Counters,
+    drop_timeout: Option<Duration>,
+    weights: Vec<u32>,      // empty unless set_weight has been called
+    deficits: Vec<i64>,     // for picking between weighted receivers
+    trace: Option<Vec<TraceEvent>>,  // None unless tracing is on
+    ordered: bool,
+    to_notify: Vec<(Arc<dyn WaitStrategy>, usize)>,  // like workers_to_unblock, for custom waits
+    spill_capacity: usize,
+    staged: VecDeque<Box<T>>,
+    burst: Option<Arc<BurstCounter>>,  // set by begin_burst
+    timestamps: bool,
+    selector: Option<Selector>,  // set by set_selector
+    selector_states: Vec<WorkerState>,  // passed to the selector; kept to save allocating
+}
+
+/// The state shared by all the handles created by `Sender::try_clone`.
+struct Registry<T> {
+    workers: Mutex<Vec<Arc<Worker<T>>>>,
+    version: AtomicUsize,   // bumped whenever `workers` changes
+    senders: AtomicUsize,   // the number of live handles
+    generation: AtomicU64,  // incremented by every call to wake_all
+    ready: Arc<Readiness>,
+    sequencer: Arc<Sequencer>,
+    spill: Arc<Spill<T>>,
+    #[cfg(feature = "hdr")]
+    latencies: Arc<Mutex<hdrhistogram::Histogram<u64>>>,
+}
+
+impl<T> Registry<T> {
+    /// Create a new receiver, and make it visible to all the handles.
+    fn add_receiver(&self, mut worker: Worker<T>, eventfd: &Arc<EventFd>) -> Receiver<T> {
+        worker.registry = self as *const Registry<T> as usize;
+        let worker = Arc::new(worker);
+        {
+            let mut workers = self.workers.lock().unwrap();
+            workers.push(worker.clone());
+            self.version.fetch_add(1, Ordering::SeqCst);
+        }
+        #[allow(unused_mut)]
+        let mut receiver = Receiver::new(worker, eventfd.clone(), self.ready.clone(), self.sequencer.clone(), self.spill.clone());
+        #[cfg(feature = "hdr")]
+        { receiver.latencies = Some(self.latencies.clone()); }
+        receiver
+    }
+}
+
+/// Payloads which were sent with `Sender::enqueue_or_spill` when every receiver was busy.
+struct Spill<T> {
+    queue: Mutex<VecDeque<Box<T>>>,
+    len: AtomicUsize,   // so that receivers can check for spilled work without locking
+}
+
+impl<T> Spill<T> {
+    fn new() -> Spill<T> {
+        Spill { queue: Mutex::new(VecDeque::new()), len: AtomicUsize::new(0) }
+    }
+
+    fn pop(&self) -> Option<Box<T>> {
+        if self.len.load(Ordering::SeqCst) == 0 { return None; }
+        let mut queue = self.queue.lock().unwrap();
+        let x = queue.pop_front();
+        self.len.store(queue.len(), Ordering::SeqCst);
+        x
+    }
+}
+
+/// Counts the payloads in a burst which haven't been finished with yet. See `Sender::begin_burst`.
+#[derive(Debug, Default)]
+struct BurstCounter {
+    remaining: Mutex<usize>,
+    cond: Condvar,
+}
+
+impl BurstCounter {
+    fn add(&self) {
+        *self.remaining.lock().unwrap() += 1;
+    }
+
+    fn done(&self) {
+        let mut remaining = self.remaining.lock().unwrap();
+        *remaining -= 1;
+        if *remaining == 0 { self.cond.notify_all(); }
+    }
+}
+
+/// Lets a producer wait for a burst to be processed. See `Sender::begin_burst`.
+#[derive(Debug, Clone)]
+pub struct BurstToken(Arc<BurstCounter>);
+
+impl BurstToken {
+    /// The number of payloads in the burst which haven't been finished with yet.
+    pub fn remaining(&self) -> usize {
+        *self.0.remaining.lock().unwrap()
+    }
+
+    /// Block until every payload in the burst has been finished with.
+    pub fn wait(&self) {
+        let mut remaining = self.0.remaining.lock().unwrap();
+        while *remaining > 0 { remaining = self.0.cond.wait(remaining).unwrap(); }
+    }
+
+    /// Like `wait`, but gives up after `timeout`. Returns whether the burst was finished.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut remaining = self.0.remaining.lock().unwrap();
+        while *remaining > 0 {
+            let now = Instant::now();
+            if now >= deadline { return false; }
+            remaining = self.0.cond.wait_timeout(remaining, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+/// Numbers the payloads sent by a sender in ordered mode, and makes the receivers process them
+/// one at a time, in that order. See `Sender::set_ordered`.
+struct Sequencer {
+    next: AtomicU64,    // the number to give the next payload
+    // The first number which hasn't been finished yet, and those after it which have been.
+    finished: Mutex<(u64, BTreeSet<u64>)>,
+    cond: Condvar,
+}
+
+/// The sequence number of a payload which wasn't sent in ordered mode.
+const NO_SEQ: u64 = u64::MAX;
+
+impl Sequencer {
+    fn new() -> Sequencer {
+        Sequencer { next: AtomicU64::new(0), finished: Mutex::new((0, BTreeSet::new())), cond: Condvar::new() }
+    }
+
+    fn stamp(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Block until all the payloads before `seq` have been finished.
+    fn wait_turn(&self, seq: u64) {
+        let mut finished = self.finished.lock().unwrap();
+        while finished.0 < seq {
+            finished = self.cond.wait(finished).unwrap();
+        }
+    }
+
+    /// Mark `seq` as finished (or as never going to be processed).
+    fn finish(&self, seq: u64) {
+        let mut finished = self.finished.lock().unwrap();
+        let (ref mut first, ref mut rest) = *finished;
+        if seq == *first {
+            *first += 1;
+            while rest.remove(first) { *first += 1; }
+            self.cond.notify_all();
+        } else {
+            rest.insert(seq);
+        }
+    }
+}
+
+/// Lets producers find out when a receiver becomes free. See `SharedSender::send_blocking` and
+/// `Sender::on_worker_ready`.
+struct Readiness {
+    waiters: AtomicUsize,
+    /// Tasks waiting in `enqueue_async`. (Threads wait on `cond`.)
+    lock: Mutex<Vec<Waker>>,
+    cond: Condvar,
+    /// Bumped (under `lock`) by every `notify` which wakes the waiters.
+    notified: AtomicU64,
+    has_callback: AtomicBool,
+    callback: Mutex<Option<ReadyCallback>>,
+}
+
+type ReadyCallback = Arc<dyn Fn(usize) + Send + Sync>;
+
+impl Readiness {
+    fn new() -> Readiness {
+        Readiness {
+            waiters: AtomicUsize::new(0),
+            lock: Mutex::new(Vec::new()),
+            cond: Condvar::new(),
+            notified: AtomicU64::new(0),
+            has_callback: AtomicBool::new(false),
+            callback: Mutex::new(None),
+        }
+    }
+
+    /// Called by the receiver with the given id when it has just started waiting. Cheap if
+    /// nobody's listening.
+    fn notify(&self, id: usize) {
+        if self.has_callback.load(Ordering::SeqCst) {
+            // Don't hold the lock while calling it, in case it's slow
+            let callback = self.callback.lock().unwrap().clone();
+            if let Some(f) = callback { f(id); }
+        }
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let mut wakers = self.lock.lock().unwrap();
+            self.notified.fetch_add(1, Ordering::SeqCst);
+            self.cond.notify_all();
+            for waker in wakers.drain(..) { waker.wake(); }
+        }
+    }
+}
+
+/// Statistics collected by a `Sender`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counters {
+    /// The number of times `enqueue` has tried to claim a receiver's slot.
+    pub cas_attempts: u64,
+    /// The number of payloads which have been handed to a receiver.
+    pub sent: u64,
+    /// The number of payloads which were returned-to-sender because no receiver was ready.
+    pub dropped: u64,
+    /// The number of times the eventfd has been written to in order to wake receivers.
+    pub wake_syscalls: u64,
+}
+
+/// What the receivers were up to at some moment. See `Sender::report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolReport {
+    pub receivers: Vec<ReceiverReport>,
+}
+
+/// One receiver's entry in a `PoolReport`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiverReport {
+    /// The receiver's position in the order they were created.
+    pub index: usize,
+    /// The receiver's state, or the raw value if it isn't a valid one.
+    pub state: Result<WorkerState, usize>,
+    /// The number of payloads it has received.
+    pub processed: u64,
+    /// The number of times it has been woken up when there was no work for it.
+    pub spurious_wakeups: u64,
+    /// Whether it's been given a payload which it hasn't taken yet.
+    pub outstanding: bool,
 }
 
 pub struct Receiver<T> {
     inner: Arc<Worker<T>>,
-    eventfd: RawFd,
-    eventfd_buf: [u8; 8],
+    ready: Arc<Readiness>,
+    eventfd: Arc<EventFd>,
+    #[cfg(feature = "epoll")]
+    epoll: Epoll,
+    expired: u64,
+    spurious_backoff: Option<(u32, Duration)>,
+    yield_strategy: YieldStrategy,
+    spin: Option<AdaptiveSpin>,
+    sequencer: Arc<Sequencer>,
+    current_seq: u64,   // the sequence number of the payload we're working on, or NO_SEQ
+    current_burst: Option<Arc<BurstCounter>>,  // the burst the payload we're working on is in
+    current_times: Option<(Instant, Instant)>,  // when it was enqueued and received, if stamped
+    latency: Option<LatencyBreakdown>,          // of the last payload we finished with
+    generation: u64,    // the wake generation of the last payload we took
+    ttl: Option<u8>,    // the hop budget of the last payload we took
+    spill: Arc<Spill<T>>,
+    history: Option<History>,
+    parked: bool,   // we've set ourselves to WAITING, and haven't taken a wakeup since
+    #[cfg(feature = "metrics")]
+    wakeup_latency: LatencyHistogram,
+    #[cfg(feature = "hdr")]
+    latencies: Option<Arc<Mutex<hdrhistogram::Histogram<u64>>>>,  // shared with the sender
+}
+
+/// How long a receiver spins before polling, adjusted according to how often spinning pays off.
+#[derive(Debug, Clone, Copy)]
+struct AdaptiveSpin {
+    spin: u32,
+    max: u32,
+    hit_rate: u32,  // moving average, out of 256
+}
+
+impl AdaptiveSpin {
+    fn new(initial: u32) -> AdaptiveSpin {
+        AdaptiveSpin { spin: initial, max: initial, hit_rate: 256 }
+    }
+
+    /// Note whether work turned up while spinning, and adjust the spin count to match.
+    fn record(&mut self, hit: bool) {
+        self.hit_rate = (self.hit_rate * 7 + if hit { 256 } else { 0 }) / 8;
+        if self.hit_rate >= 128 {
+            self.spin = cmp::min(cmp::max(self.spin * 2, 1), self.max);
+        } else {
+            self.spin /= 2;
+        }
+    }
+}
+
+/// A way for receivers to wait for work, other than the eventfd. See
+/// `Sender::mk_receiver_with_wait`.
+pub trait WaitStrategy: Send + Sync {
+    /// Block until `state` is no longer `expected` (or until `notify` is called). Returning early
+    /// is allowed: the receiver checks the state and waits again.
+    fn wait(&self, state: &AtomicUsize, expected: usize);
+    /// Called by `wake_all`, after `n` payloads have been sent to receivers waiting with this.
+    fn notify(&self, n: usize);
+}
+
+/// Busy-waits on the state. Used by `Sender::mk_receiver_spin_only`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpinWait;
+
+impl WaitStrategy for SpinWait {
+    fn wait(&self, state: &AtomicUsize, expected: usize) {
+        while state.load(Ordering::SeqCst) == expected { hint::spin_loop(); }
+    }
+
+    fn notify(&self, _n: usize) {}
+}
+
+/// What a receiver does when it's woken up but the work wasn't for it, before polling again.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum YieldStrategy {
+    /// Go straight back to polling.
+    None,
+    /// Call `thread::yield_now`, giving the other receivers a chance to check their slots. This
+    /// is the default.
+    #[default]
+    Yield,
+    /// Sleep for the given duration.
+    Sleep(Duration),
+}
+
+/// The eventfd shared by a sender and its receivers. It's closed when they're all gone.
+///
+/// Also keeps track of what the counter ought to be, based on the reads and writes made through
+/// it (but not by anyone else who has the fd).
+struct EventFd(RawFd, AtomicI64);
+
+impl EventFd {
+    fn new(fd: RawFd, initial: i64) -> EventFd {
+        EventFd(fd, AtomicI64::new(initial))
+    }
+
+    /// Add `n` to the counter.
+    fn write(&self, n: i64) -> io::Result<()> {
+        let mut buf = [0; 8];
+        NativeEndian::write_i64(&mut buf, n);
+        sys_write(self.0, &buf)?;
+        self.1.fetch_add(n, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Take one from the counter, blocking until it's non-zero. (In counter mode, take
+    /// everything.) Returns the amount taken.
+    fn read(&self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        sys_read(self.0, &mut buf)?;
+        let n = NativeEndian::read_u64(&buf);
+        self.1.fetch_sub(n as i64, Ordering::SeqCst);
+        Ok(n)
+    }
+
+    /// What the counter should be.
+    fn balance(&self) -> i64 {
+        self.1.load(Ordering::SeqCst)
+    }
+
+    /// Whether the counter is non-zero. Doesn't block.
+    fn is_readable(&self) -> io::Result<bool> {
+        sys_poll(self.0, 0)
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0); }
+    }
+}
+
+// Thin wrappers around the syscalls we make. Calls which are interrupted by a signal are
+// restarted (from the beginning, in the case of timeouts).
+
+/// Call `f` until it isn't interrupted, turning a negative return value into `errno`.
+fn retry<F: FnMut() -> isize>(mut f: F) -> io::Result<usize> {
+    loop {
+        let ret = f();
+        if ret >= 0 { return Ok(ret as usize); }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted { return Err(err); }
+    }
+}
+
+fn sys_eventfd(initial: u32, flags: libc::c_int) -> io::Result<RawFd> {
+    retry(|| unsafe { libc::eventfd(initial, flags) as isize }).map(|fd| fd as RawFd)
+}
+
+fn sys_read(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    retry(|| unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) })
+}
+
+fn sys_write(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    retry(|| unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) })
+}
+
+/// Wait for `fd` to become readable, for up to `timeout_ms` (negative means forever). Returns
+/// whether it did.
+fn sys_poll(fd: RawFd, timeout_ms: libc::c_int) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    retry(|| unsafe { libc::poll(&mut pollfd, 1, timeout_ms) as isize }).map(|n| n > 0)
+}
+
+/// An epoll instance watching an eventfd, edge-triggered.
+///
+/// Unlike `poll`, this only wakes up when the eventfd is written to, rather than whenever it's
+/// non-zero, so a receiver which was woken for someone else's work doesn't wake up again until
+/// the next `wake_all`.
+#[cfg(feature = "epoll")]
+struct Epoll(RawFd);
+
+#[cfg(feature = "epoll")]
+impl Epoll {
+    fn new(eventfd: &EventFd) -> Epoll {
+        let fd = retry(|| unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) as isize }).unwrap();
+        let epoll = Epoll(fd as RawFd);
+        let mut event = libc::epoll_event { events: (libc::EPOLLIN | libc::EPOLLET) as u32, u64: 0 };
+        retry(|| unsafe { libc::epoll_ctl(epoll.0, libc::EPOLL_CTL_ADD, eventfd.0, &mut event) as isize }).unwrap();
+        epoll
+    }
+
+    /// Wait for the eventfd to be written to, for up to `timeout_ms` (negative means forever).
+    /// Returns whether it was.
+    fn wait(&self, timeout_ms: libc::c_int) -> io::Result<bool> {
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        retry(|| unsafe { libc::epoll_wait(self.0, &mut event, 1, timeout_ms) as isize }).map(|n| n > 0)
+    }
+}
+
+#[cfg(feature = "epoll")]
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0); }
+    }
 }
 
 unsafe impl<T: Send> Send for Sender<T> {}
 unsafe impl<T: Send> Send for Receiver<T> {}
 
-struct Worker<T> {
+/// The state which a receiver shares with its sender: a slot for the payload, and the state
+/// machine which guards it.
+///
+/// You only need to deal with this directly when using `Receiver::from_raw_fd`.
+// Each worker gets its own cache lines, so that a receiver updating its state doesn't slow down
+// the sender's CAS on the next receiver along.
+#[repr(align(64))]
+pub struct Worker<T> {
     state: AtomicUsize,
     slot: AtomicPtr<T>,
+    // Only touched by whoever owns the slot, as determined by the state machine: the sender
+    // writes it before filling the slot, and the receiver reads it after emptying the slot.
+    deadline: UnsafeCell<Option<Instant>>,
+    ttl: UnsafeCell<Option<u8>>,    // from enqueue_ttl. Owned like the deadline
+    // Payloads sent with `enqueue_value` which are small enough live here, and the slot points at
+    // it. Owned by the same party as the deadline.
+    inline: UnsafeCell<[MaybeUninit<usize>; INLINE_WORDS]>,
+    // Payloads sent with `enqueue_fn` are made by the receiver, using this. The slot points at
+    // it. Owned by the same party as the deadline.
+    maker: UnsafeCell<Option<Box<dyn FnOnce() -> T + Send>>>,
+    // The sender's generation at the time the slot was last filled. If it's still the sender's
+    // current generation, then wake_all hasn't been called since.
+    generation: AtomicU64,
+    // Bumped every time the slot is filled, so that an `EnqueueToken` can tell whether the
+    // payload it refers to is still the one in the slot.
+    ticket: AtomicU64,
+    spurious: AtomicU64,    // wakeups which turned out to be for someone else
+    processed: AtomicU64,   // payloads returned from recv
+    orphan_seen: AtomicBool, // the receiver has returned RecvError::Orphaned
+    id: usize,
+    // The address of the registry it was added to, so that `steal_from` can tell whether it
+    // belongs to the same sender. Zero for workers passed to `Receiver::from_raw_fd`.
+    registry: usize,
+    tag: Option<Box<dyn Any + Send + Sync>>,  // from mk_receiver_tagged
+    // Set by mk_receiver_with_wait. If so, the receiver never touches the eventfd
+    waiter: Option<Arc<dyn WaitStrategy>>,
+    seq: AtomicU64,     // the payload's sequence number, or NO_SEQ. Owned like the deadline
+    burst: UnsafeCell<Option<Arc<BurstCounter>>>,  // the burst the payload belongs to, if any
+    enqueued_at: UnsafeCell<Option<Instant>>,   // when the payload was enqueued, in timestamp mode
 }
 
-// Receiver states
-const RS_WAITING:  usize = 0;   // This receiver has no work to do, and is blocking
-const RS_PENDING:  usize = 1;   // This receiver has work to do, but hasn't unblocked yet
-const RS_RUNNING:  usize = 2;   // This receiver is running and is doing some work
-const RS_ORPHANED: usize = 3;   // The sender has gone away, never to return
-// TODO: const RS_INTEND_TO_DROP // The receiver wants to be dropped from the pool
+unsafe impl<T: Send> Send for Worker<T> {}
+unsafe impl<T: Send> Sync for Worker<T> {}
 
-impl<T> Sender<T> {
-    pub fn new() -> Sender<T> {
-        Sender {
-            eventfd: eventfd(0, EFD_SEMAPHORE).unwrap(),
-            workers: vec![],
-            next_worker: 0,
-            workers_to_unblock: 0,
-            eventfd_buf: [0;8],
-        }
+impl<T> Default for Worker<T> {
+    fn default() -> Worker<T> {
+        Worker::new()
     }
+}
 
-    /// Create a new receiver handle.
-    pub fn mk_receiver(&mut self) -> Receiver<T> {
-        let worker = Arc::new(Worker {
-            state: AtomicUsize::new(RS_RUNNING),
+impl<T> Worker<T> {
+    /// Create an empty worker, in the state a fresh receiver starts in.
+    pub fn new() -> Worker<T> {
+        Worker {
+            state: AtomicUsize::new(WorkerState::Running as usize),
             slot: AtomicPtr::new(ptr::null_mut()),
-        });
-        self.workers.push(worker.clone());
-        Receiver {
-            inner: worker,
-            eventfd: self.eventfd,
-            eventfd_buf: [0; 8],
+            deadline: UnsafeCell::new(None),
+            ttl: UnsafeCell::new(None),
+            inline: UnsafeCell::new([MaybeUninit::uninit(); INLINE_WORDS]),
+            maker: UnsafeCell::new(None),
+            generation: AtomicU64::new(0),
+            ticket: AtomicU64::new(0),
+            spurious: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            orphan_seen: AtomicBool::new(false),
+            id: NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed),
+            registry: 0,
+            tag: None,
+            waiter: None,
+            seq: AtomicU64::new(NO_SEQ),
+            burst: UnsafeCell::new(None),
+            enqueued_at: UnsafeCell::new(None),
         }
     }
 
-    /// Wake up *all* reciever threads.
-    ///
-    /// This function is guaranteed to wake up all the threads. If some threads are already
-    /// running, then those threads, or others, may be woken up spuriously in the future as a
-    /// result.
+    /// Whether a `T` can be stored in the inline buffer.
+    fn fits_inline() -> bool {
+        mem::size_of::<T>() <= mem::size_of::<[usize; INLINE_WORDS]>()
+            && mem::align_of::<T>() <= mem::align_of::<usize>()
+    }
+
+    fn inline_ptr(&self) -> *mut T {
+        self.inline.get() as *mut T
+    }
+
+    /// Give up the place in the order held by the payload in the slot (if any), because it's been
+    /// taken back. It no longer counts towards its burst either.
     ///
-    /// This function does not block, but it does make a (single) syscall.
-    pub fn wake_all(&mut self) {
-        NativeEndian::write_i64(&mut self.eventfd_buf[..], self.workers_to_unblock);
-        self.workers_to_unblock = 0;
-        write(self.eventfd, &self.eventfd_buf).unwrap();
+    /// Only the slot's owner may call this.
+    fn abandon(&self, sequencer: &Sequencer) {
+        let seq = self.seq.swap(NO_SEQ, Ordering::SeqCst);
+        if seq != NO_SEQ { sequencer.finish(seq); }
+        if let Some(burst) = unsafe { (*self.burst.get()).take() } { burst.done(); }
+        unsafe { *self.enqueued_at.get() = None; }
     }
-}
 
-impl<T: Send> Sender<T> {
-    /// Attempt to send a payload to a waiting receiver.
+    /// Put `x` in the slot, if it's empty. Otherwise you get `x` back.
+    fn slot_put(&self, x: *mut T) -> Result<(), *mut T> {
+        self.slot.compare_exchange(ptr::null_mut(), x, Ordering::SeqCst, Ordering::SeqCst)
+            .map(|_| ()).map_err(|_| x)
+    }
+
+    /// Empty the slot, returning what was in it (if anything). However many threads race to do
+    /// this, only one of them gets the pointer.
+    fn slot_take(&self) -> Option<*mut T> {
+        let ptr = self.slot.swap(ptr::null_mut(), Ordering::SeqCst);
+        if ptr.is_null() { None } else { Some(ptr) }
+    }
+
+    /// Empty the slot, and take ownership of the payload.
     ///
-    /// `enqueue` will only succeed if there is a receiver ready to take the value *right now*. If no
-    /// receivers are ready, the value is returned-to-sender.
+    /// Safety: the caller must own the slot, as determined by the state machine, so that nobody
+    /// refills the inline buffer while it's being read.
+    unsafe fn slot_take_box(&self) -> Option<Box<T>> {
+        self.slot_take().map(|ptr| self.unpack_box(ptr))
+    }
+
+    /// Not a real `T`: it means the payload's still to be made.
+    fn maker_ptr(&self) -> *mut T {
+        self.maker.get() as *mut T
+    }
+
+    /// Take ownership of a payload taken out of the slot, boxing it if it was stored inline.
     ///
-    /// Note: `enqueue` will **not** unblock the receiver it sends the payload to. You must call
-    /// `wake_all` after calling `enqueue`!
+    /// Safety: `ptr` must have come out of this worker's slot, and the caller must own it.
+    unsafe fn unpack_box(&self, ptr: *mut T) -> Box<T> {
+        if ptr == self.inline_ptr() || ptr == self.maker_ptr() {
+            Box::new(self.unpack_value(ptr))
+        } else {
+            Box::from_raw(ptr)
+        }
+    }
+
+    /// Take ownership of a payload taken out of the slot, by value.
     ///
-    /// This function does not block or make any syscalls.
-    pub fn enqueue(&mut self, x: Box<T>) -> Option<Box<T>> {
-        // 1. Find a receiver in WAITING state
-        // 2. Write ptr to that receiver's slot
-        // 3. Set that receiver to PENDING state
-        // 4. Note that we need to increment eventfd
+    /// Safety: as for `unpack_box`.
+    unsafe fn unpack_value(&self, ptr: *mut T) -> T {
+        if ptr == self.inline_ptr() {
+            ptr::read(ptr)
+        } else if ptr == self.maker_ptr() {
+            let make = (*self.maker.get()).take().expect("recv: no payload maker. Please report this error.");
+            make()
+        } else {
+            *Box::from_raw(ptr)
+        }
+    }
 
-        let mut target_worker = None;
-        for i in 0..self.workers.len() {
-            let i2 = (i + self.next_worker) % self.workers.len();
-            match self.workers[i2].state.compare_and_swap(RS_WAITING, RS_PENDING, Ordering::SeqCst) {
-                RS_WAITING => {
-                    /* it was ready */
-                    target_worker = Some(i2);
-                    break;
-                }
-                RS_PENDING | RS_RUNNING => { /* it's busy */ }
-                x => panic!("enqueue: bad state ({}). Please report this error.", x),
-            }
+    /// The current state, or the raw value if it isn't a valid one.
+    fn state(&self) -> Result<WorkerState, usize> {
+        WorkerState::try_from(self.state.load(Ordering::SeqCst))
+    }
+
+    fn set_state(&self, new: WorkerState) {
+        self.state.store(new as usize, Ordering::SeqCst);
+    }
+
+    /// Returns the previous state.
+    fn swap_state(&self, new: WorkerState) -> Result<WorkerState, usize> {
+        WorkerState::try_from(self.state.swap(new as usize, Ordering::SeqCst))
+    }
+
+    /// Returns the previous state, whether or not the swap happened.
+    fn cas_state(&self, current: WorkerState, new: WorkerState) -> Result<WorkerState, usize> {
+        let (current, new) = (current as usize, new as usize);
+        match self.state.compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(x) | Err(x) => WorkerState::try_from(x),
         }
-        match target_worker {
-            Some(i) => {
-                let ptr = self.workers[i].slot.swap(Box::into_raw(x), Ordering::SeqCst);
-                assert!(ptr.is_null(), "enqueue: slot contains non-null ptr. Please report this error.");
-                self.next_worker = (i + 1) % self.workers.len();
-                self.workers_to_unblock += 1;
-                None
-            }
-            None => Some(x)
+    }
+}
+
+impl<T> Drop for Worker<T> {
+    /// Drops the payload left in the slot, if any: eg. one which was enqueued but never
+    /// received before the sender and receiver went away.
+    fn drop(&mut self) {
+        if let Some(ptr) = self.slot_take() {
+            // A payload which is still to be made is dropped along with its maker
+            if ptr != self.maker_ptr() { unsafe { drop(self.unpack_value(ptr)); } }
         }
     }
 }
 
-impl<T> Receiver<T> {
-    /// Blocks until (1) a message is sent to this `Receiver`, and (2) wake_all() is called on the
-    /// associated `Sender`.
-    pub fn recv(&mut self) -> Result<Box<T>, RecvError> {
-        // 1. Set state to WAITING
-        // 2. Block on eventfd
-        // 3. Check state to make sure it's PENDING
-        // 4. If so, change state to RUNNING
-        // 5. Decrement the eventfd
-        // 6. Take ptr from slot and return
+static NEXT_WORKER_ID: AtomicUsize = AtomicUsize::new(0);
 
-        // This function always leaves the state as RUNNING or ORPHANED.
-        // The sender is allowed to (A) swap the state from WAITING to PENDING, and (B) set the
-        // state to ORPHANED.
-        // Therefore, when entering this function, the state must be RUNNING or ORPHANED.
-        match self.inner.state.compare_and_swap(RS_RUNNING, RS_WAITING, Ordering::SeqCst) {
-            RS_RUNNING => { /* things looks good. onward! */ }
-            RS_ORPHANED => { return Err(RecvError::Orphaned); }
-            x => panic!("recv::1: bad state ({}). Please report this error.", x),
+/// Payloads which fit in this many words (and are no more aligned than a word) can be sent with
+/// `Sender::enqueue_value` without allocating.
+pub const INLINE_WORDS: usize = 3;
+
+/// Receiver states
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerState {
+    Waiting = 0,    // This receiver has no work to do, and is blocking
+    Pending = 1,    // This receiver has work to do, but hasn't unblocked yet
+    Running = 2,    // This receiver is running and is doing some work
+    Orphaned = 3,   // The sender has gone away, never to return
+    Locked = 4,     // Someone is filling, inspecting or stealing this receiver's unwoken payload
+    Dead = 5,       // The receiver has been dropped
+}
+
+impl TryFrom<usize> for WorkerState {
+    type Error = usize;
+    fn try_from(x: usize) -> Result<WorkerState, usize> {
+        match x {
+            0 => Ok(WorkerState::Waiting),
+            1 => Ok(WorkerState::Pending),
+            2 => Ok(WorkerState::Running),
+            3 => Ok(WorkerState::Orphaned),
+            4 => Ok(WorkerState::Locked),
+            5 => Ok(WorkerState::Dead),
+            x => Err(x),
         }
-        let mut pollfds = [PollFd::new(self.eventfd, POLLIN)];
-        loop {
-            // Block until eventfd becomes non-zero
-            poll(&mut pollfds, -1).unwrap();
-            match self.inner.state.compare_and_swap(RS_PENDING, RS_RUNNING, Ordering::SeqCst) {
-                RS_PENDING => /* this was a genuine wakeup. let's do some work! */ break,
-                RS_WAITING =>
-                    // A wakeup was sent, but it was intended for someone else. First, we let the
-                    // other threads check if the wakeup was for them...
-                    thread::yield_now(),
-                    // ...and now we go back to blocking on eventfd
-                RS_ORPHANED => return Err(RecvError::Orphaned),
-                x => panic!("recv::2: bad state ({}). Please report this error.", x),
-            }
+    }
+}
+
+impl WorkerState {
+    fn name(self) -> &'static str {
+        match self {
+            WorkerState::Waiting => "waiting",
+            WorkerState::Pending => "pending",
+            WorkerState::Running => "running",
+            WorkerState::Orphaned => "orphaned",
+            WorkerState::Locked => "locked",
+            WorkerState::Dead => "dead",
         }
-        // Decrement the eventfd to show that one of the inteded workers got the message.
-        // FIXME: This additional syscall is quite painful :-(
-        read(self.eventfd, &mut self.eventfd_buf).unwrap();
-        let ptr = self.inner.slot.swap(ptr::null_mut(), Ordering::SeqCst);
-        assert!(!ptr.is_null(), "recv: slot contains null ptr. Please report this error.");
-        unsafe { Ok(Box::from_raw(ptr)) }
     }
 }
 
-impl<T> Drop for Sender<T> {
-    /// All receivers will unblock with `RecvError::Orphaned`.
-    fn drop(&mut self) {
-        // Inform the receivers that the sender is going away.
-        for w in self.workers.iter_mut() {
-            w.state.store(RS_ORPHANED, Ordering::SeqCst);
+/// Something a receiver did which can change its state. See `Receiver::with_history`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Started waiting for work (having finished the previous payload, if any)
+    Wait,
+    /// Was woken up, whether or not there was work for it
+    Wake,
+}
+
+/// The last few states a receiver saw, oldest first.
+#[derive(Debug)]
+struct History {
+    ops: VecDeque<(Op, WorkerState)>,
+    len: usize,
+}
+
+impl History {
+    fn record(&mut self, op: Op, state: WorkerState) {
+        if self.len == 0 { return; }
+        if self.ops.len() == self.len { self.ops.pop_front(); }
+        self.ops.push_back((op, state));
+    }
+}
+
+/// The raw value of a state, for error messages.
+fn raw_state(x: Result<WorkerState, usize>) -> usize {
+    match x {
+        Ok(state) => state as usize,
+        Err(x) => x,
+    }
+}
+
+/// Options for the eventfd which a `Sender` uses to wake its receivers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SenderConfig {
+    /// Create the eventfd with `EFD_SEMAPHORE`. The wakeup protocol relies on each woken receiver
+    /// decrementing the counter by exactly one, so turning this off is only sound with a single
+    /// receiver.
+    pub semaphore: bool,
+    /// The initial value of the eventfd's counter.
+    pub initial: u32,
+    /// Create the eventfd with `EFD_CLOEXEC`.
+    pub cloexec: bool,
+    /// Create the eventfd with `EFD_NONBLOCK`.
+    pub nonblock: bool,
+}
+
+impl Default for SenderConfig {
+    fn default() -> SenderConfig {
+        SenderConfig {
+            semaphore: true,
+            initial: 0,
+            cloexec: false,
+            nonblock: false,
         }
-        NativeEndian::write_i64(&mut self.eventfd_buf[..], 1);
-        write(self.eventfd, &self.eventfd_buf).unwrap();
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum RecvError {
-    Orphaned,
+impl SenderConfig {
+    fn flags(&self) -> libc::c_int {
+        let mut flags = 0;
+        if self.semaphore { flags |= libc::EFD_SEMAPHORE; }
+        if self.cloexec { flags |= libc::EFD_CLOEXEC; }
+        if self.nonblock { flags |= libc::EFD_NONBLOCK; }
+        flags
+    }
 }
 
-#[cfg(test)]
-mod tests {
+impl<T> Sender<T> {
+    /// Create a sender with no receivers.
+    ///
+    /// # Panics
+    ///
+    /// If the eventfd can't be created (eg. because the process has run out of fds). Use
+    /// `try_new` to handle this.
+    pub fn new() -> Sender<T> {
+        Sender::try_new().unwrap()
+    }
+
+    /// Like `new`, but returns an error if the eventfd can't be created.
+    pub fn try_new() -> io::Result<Sender<T>> {
+        Sender::try_with_config(SenderConfig::default())
+    }
+
+    /// Create a sender whose eventfd is set up according to `config`.
+    ///
+    /// # Panics
+    ///
+    /// As for `new`.
+    pub fn with_config(config: SenderConfig) -> Sender<T> {
+        Sender::try_with_config(config).unwrap()
+    }
+
+    /// Like `with_config`, but returns an error if the eventfd can't be created.
+    pub fn try_with_config(config: SenderConfig) -> io::Result<Sender<T>> {
+        let fd = sys_eventfd(config.initial, config.flags())?;
+        Ok(Sender::from_eventfd(Arc::new(EventFd::new(fd, config.initial as i64))))
+    }
+
+    /// A sender with no receivers, using the given eventfd.
+    fn from_eventfd(eventfd: Arc<EventFd>) -> Sender<T> {
+        Sender {
+            eventfd,
+            registry: Arc::new(Registry {
+                workers: Mutex::new(vec![]),
+                version: AtomicUsize::new(0),
+                senders: AtomicUsize::new(1),
+                generation: AtomicU64::new(0),
+                ready: Arc::new(Readiness::new()),
+                sequencer: Arc::new(Sequencer::new()),
+                spill: Arc::new(Spill::new()),
+                #[cfg(feature = "hdr")]
+                latencies: Arc::new(Mutex::new(hdrhistogram::Histogram::new(3).unwrap())),
+            }),
+            workers: vec![],
+            version: 0,
+            next_worker: 0,
+            last_worker: None,
+            workers_to_unblock: 0,
+            counters: Counters::default(),
+            drop_timeout: None,
+            weights: vec![],
+            deficits: vec![],
+            trace: None,
+            ordered: false,
+            to_notify: vec![],
+            spill_capacity: 0,
+            staged: VecDeque::new(),
+            burst: None,
+            timestamps: false,
+            selector: None,
+            selector_states: vec![],
+        }
+    }
+
+    /// Create another handle to this sender, for handing off to a different producer.
+    ///
+    /// The new handle shares the eventfd and the set of receivers: receivers created by either
+    /// handle can be sent to using the other. Each handle goes round-robin independently, and
+    /// `wake_all` only wakes the receivers which the same handle enqueued to.
+    ///
+    /// The handles must not be used concurrently. The intended use is for a primary and a backup
+    /// producer which take turns; when switching, call `wake_all` before passing control over.
+    /// The receivers are orphaned when the last handle is dropped.
+    pub fn try_clone(&self) -> Sender<T> {
+        self.registry.senders.fetch_add(1, Ordering::SeqCst);
+        Sender {
+            eventfd: self.eventfd.clone(),
+            registry: self.registry.clone(),
+            workers: self.workers.clone(),
+            version: self.version,
+            next_worker: 0,
+            last_worker: None,
+            workers_to_unblock: 0,
+            counters: Counters::default(),
+            drop_timeout: None,
+            weights: self.weights.clone(),
+            deficits: vec![],
+            trace: None,
+            ordered: self.ordered,
+            to_notify: vec![],
+            spill_capacity: self.spill_capacity,
+            staged: VecDeque::new(),
+            burst: None,
+            timestamps: self.timestamps,
+            selector: None,
+            selector_states: vec![],
+        }
+    }
+
+    /// Make dropping this sender wait until the receivers which were blocked in `recv` have
+    /// noticed that they're orphaned, for up to `timeout`.
+    ///
+    /// By default the drop returns as soon as the receivers have been told. This only has an
+    /// effect on the last handle to be dropped.
+    pub fn set_drop_timeout(&mut self, timeout: Duration) {
+        self.drop_timeout = Some(timeout);
+    }
+
+    /// Pick up any receivers which were created by other handles.
+    fn refresh(&mut self) {
+        let version = self.registry.version.load(Ordering::SeqCst);
+        if version != self.version {
+            // Reuses our allocation, if it's big enough
+            self.workers.clone_from(&self.registry.workers.lock().unwrap());
+            self.version = version;
+            // Receivers may have been removed, so our indices could be stale
+            self.last_worker = None;
+        }
+    }
+
+    /// Create a new receiver handle.
+    pub fn mk_receiver(&mut self) -> Receiver<T> {
+        let receiver = self.registry.add_receiver(Worker::new(), &self.eventfd);
+        self.refresh();
+        receiver
+    }
+
+    /// Create a new receiver handle which busy-waits for work, instead of blocking on the eventfd.
+    ///
+    /// The receiver notices a payload as soon as it's enqueued, so sending to it doesn't need
+    /// `wake_all`, and `wake_all` makes no syscall if only spin-only receivers have been sent to.
+    /// In exchange, the receiver's thread uses a whole core the entire time it's in `recv`.
+    pub fn mk_receiver_spin_only(&mut self) -> Receiver<T> {
+        self.mk_receiver_with_wait(Arc::new(SpinWait))
+    }
+
+    /// Create a new receiver handle which waits for work using `strategy`, instead of the
+    /// eventfd.
+    ///
+    /// `wake_all` calls `strategy.notify` once, with the number of payloads sent to receivers
+    /// using it since last time. Several receivers can share a strategy.
+    pub fn mk_receiver_with_wait(&mut self, strategy: Arc<dyn WaitStrategy>) -> Receiver<T> {
+        let mut worker = Worker::new();
+        worker.waiter = Some(strategy);
+        let receiver = self.registry.add_receiver(worker, &self.eventfd);
+        self.refresh();
+        receiver
+    }
+
+    /// Create a new receiver handle, labelled with `tag`. See `enqueue_matching`.
+    pub fn mk_receiver_tagged<Tag: Any + Send + Sync>(&mut self, tag: Tag) -> Receiver<T> {
+        let mut worker = Worker::new();
+        worker.tag = Some(Box::new(tag));
+        let receiver = self.registry.add_receiver(worker, &self.eventfd);
+        self.refresh();
+        receiver
+    }
+
+    /// Make room for at least `additional` more receivers, so that creating them doesn't
+    /// reallocate.
+    pub fn reserve(&mut self, additional: usize) {
+        self.registry.workers.lock().unwrap().reserve(additional);
+        self.workers.reserve(additional);
+    }
+
+    /// Create a sender for a different payload type which shares this one's eventfd.
+    ///
+    /// The new sender starts off with no receivers. Sharing the eventfd between senders is fine:
+    /// each receiver only takes a wakeup from it once it's seen work in its own slot.
+    pub fn reuse_eventfd<U>(&self) -> Sender<U> {
+        Sender::from_eventfd(self.eventfd.clone())
+    }
+
+    /// Turn this into a sender for a different payload type, keeping the eventfd.
+    ///
+    /// Along with the new sender you get a function which turns each of the old receivers into
+    /// a receiver for the new sender, so that worker threads can be re-tasked rather than
+    /// respawned. This sender is dropped, so (once any other handles are gone too) the old
+    /// receivers' `recv` returns `RecvError::Orphaned`; that's the worker's cue to convert its
+    /// receiver.
+    ///
+    /// Panics if any receiver is holding a payload it hasn't taken yet. Use `drain_unwoken` to
+    /// take them back first.
+    pub fn into_retyped<U>(self) -> (Sender<U>, impl Fn(Receiver<T>) -> Receiver<U> + Clone + Send + Sync)
+    where U: Send
+    {
+        for w in self.registry.workers.lock().unwrap().iter() {
+            assert!(w.state() != Ok(WorkerState::Pending), "into_retyped: a receiver has a pending payload");
+        }
+        let sender = self.reuse_eventfd::<U>();
+        let (registry, eventfd) = (sender.registry.clone(), sender.eventfd.clone());
+        ::std::mem::drop(self);
+        (sender, move |_old| registry.add_receiver(Worker::new(), &eventfd))
+    }
+
+    /// Stop sending to a receiver, and orphan it: its next (or current) call to `recv` returns
+    /// `RecvError::Orphaned`. If the receiver was holding a payload which it hadn't taken yet, you
+    /// get it back.
+    fn remove_worker(&mut self, worker: &Arc<Worker<T>>) -> Option<Box<T>> {
+        {
+            let mut workers = self.registry.workers.lock().unwrap();
+            workers.retain(|w| !Arc::ptr_eq(w, worker));
+            self.registry.version.fetch_add(1, Ordering::SeqCst);
+        }
+        self.refresh();
+        // The eventfd stays balanced in the same way as when the sender is dropped: the receiver
+        // will take one from it if it wakes up to find itself orphaned.
+        match worker.swap_state(WorkerState::Orphaned) {
+            // A thief which has LOCKED the receiver takes the payload's wakeup, but the receiver
+            // itself is still parked, and needs one of its own
+            Ok(WorkerState::Waiting) | Ok(WorkerState::Locked) => {
+                if worker.waiter.is_none() { let _ = self.eventfd.write(1); }
+                None
+            }
+            Ok(WorkerState::Pending) => {
+                // The receiver never got as far as taking the payload, and now it never will.
+                // Its wakeup has been (or will be) written already.
+                // Another handle may have reaped it already, now that it's ORPHANED
+                let x = unsafe { worker.slot_take_box() }?;
+                worker.abandon(&self.registry.sequencer);
+                Some(x)
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace all the receivers with `n` new ones, which are passed to `start`.
+    ///
+    /// `start` should get each new receiver going (eg. on a new thread running the new version
+    /// of your worker code). Once it has, the old receivers are orphaned: those which are busy
+    /// finish what they're doing, and get `RecvError::Orphaned` next time they call `recv`. You get
+    /// back the payloads which old receivers had been given but hadn't started on, so that you
+    /// can send them again. From then on, only the new receivers are sent work.
+    ///
+    /// Receiver weights are reset.
+    pub fn rotate_receivers<F: FnMut(Receiver<T>)>(&mut self, n: usize, mut start: F) -> Vec<Box<T>> {
+        let mut xs = self.compact();
+        let old = self.workers.clone();
+        for _ in 0..n { start(self.mk_receiver()); }
+        xs.extend(old.iter().filter_map(|w| self.remove_worker(w)));
+        self.weights.clear();
+        self.deficits.clear();
+        self.next_worker = 0;
+        xs
+    }
+
+    /// Create one receiver handle per available core.
+    ///
+    /// Performance degrades badly once there are more receivers than cores, so this is a safe
+    /// default size for a pool. If the number of cores can't be determined, a single receiver is
+    /// created.
+    pub fn mk_receiver_per_core(&mut self) -> Vec<Receiver<T>> {
+        (0..num_cores()).map(|_| self.mk_receiver()).collect()
+    }
+
+    /// Set the index of the receiver which the next call to `enqueue` will try first.
+    ///
+    /// Receivers are indexed in the order they were created by `mk_receiver`. Normally `enqueue`
+    /// goes round-robin, starting after the last receiver it sent to; this is mostly useful for
+    /// making tests deterministic.
+    pub fn set_next_worker(&mut self, idx: usize) {
+        self.refresh();
+        assert!(idx < self.workers.len(), "set_next_worker: index out of bounds");
+        self.next_worker = idx;
+        self.last_worker = None;
+    }
+
+    /// Give the receiver with index `idx` a share of the work proportional to `weight`.
+    ///
+    /// Receivers start with a weight of 1. Once any weight has been set, `enqueue` chooses among
+    /// the waiting receivers by weighted round-robin, rather than trying the last receiver first
+    /// and then the rest in order. Weights only bias the choice between receivers which are
+    /// waiting: a busy receiver is never sent to, whatever its weight.
+    pub fn set_weight(&mut self, idx: usize, weight: u32) {
+        self.refresh();
+        assert!(idx < self.workers.len(), "set_weight: index out of bounds");
+        assert!(weight > 0, "set_weight: weight must be non-zero");
+        if self.weights.len() <= idx { self.weights.resize(idx + 1, 1); }
+        self.weights[idx] = weight;
+    }
+
+    /// Let `select` choose which receiver `enqueue` sends to, instead of the built-in
+    /// round-robin (or weighted round-robin).
+    ///
+    /// `select` is given the states of all the receivers, indexed as for `set_weight`, and
+    /// returns the index of the one to send to, or `None` to refuse the payload. If it picks a
+    /// receiver which isn't waiting, or an index which is out of range, the payload is refused;
+    /// if the receiver stops waiting between `select` looking and the payload being placed,
+    /// `select` is asked again.
+    ///
+    /// The selector belongs to this handle: `try_clone` doesn't copy it.
+    pub fn set_selector<F>(&mut self, select: F) where F: FnMut(&[WorkerState]) -> Option<usize> + Send + 'static {
+        self.selector = Some(Box::new(select));
+    }
+
+    /// Go back to choosing receivers with the built-in round-robin.
+    pub fn clear_selector(&mut self) {
+        self.selector = None;
+    }
+
+    /// The number of payloads each receiver has received so far, indexed in the order they were
+    /// created.
+    pub fn processed_counts(&mut self) -> Vec<u64> {
+        self.refresh();
+        self.workers.iter().map(|w| w.processed.load(Ordering::Relaxed)).collect()
+    }
+
+    /// A snapshot of what every receiver is up to, indexed in the order they were created.
+    ///
+    /// Unlike `processed_counts`, this includes receivers created by other handles.
+    pub fn report(&self) -> PoolReport {
+        let workers = self.registry.workers.lock().unwrap();
+        let receivers = workers.iter().enumerate().map(|(index, w)| ReceiverReport {
+            index,
+            state: w.state(),
+            processed: w.processed.load(Ordering::Relaxed),
+            spurious_wakeups: w.spurious.load(Ordering::Relaxed),
+            outstanding: !w.slot.load(Ordering::Relaxed).is_null(),
+        }).collect();
+        PoolReport { receivers }
+    }
+
+    /// Statistics about this sender's activity so far.
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// The index of the receiver which `enqueue` would send to if it were called now, if any.
+    ///
+    /// Nothing is changed. This is only a hint: the receiver may become busy (or another may
+    /// become free) before you act on it. Receivers created by other handles since this one last
+    /// enqueued aren't considered.
+    ///
+    /// This function does not block or make any syscalls.
+    pub fn can_enqueue(&self) -> Option<usize> {
+        let ready = |i: usize| self.workers[i].state.load(Ordering::Relaxed) == WorkerState::Waiting as usize;
+        if let Some(i) = self.last_worker.filter(|&i| ready(i)) { return Some(i); }
+        let n = self.workers.len();
+        (0..n).map(|i| (i + self.next_worker) % n).find(|&i| ready(i))
+    }
+
+    /// The number of payloads which have been placed with receivers but not yet taken by them.
+    ///
+    /// This is a snapshot which may be stale by the time you read it. Receivers created by other
+    /// handles since this one last enqueued aren't counted.
+    pub fn outstanding(&self) -> usize {
+        self.workers.iter().filter(|w| !w.slot.load(Ordering::Relaxed).is_null()).count()
+    }
+
+    /// Block until at least `n` receivers are waiting for work, or until `timeout` has elapsed.
+    /// Returns the number of receivers which were waiting.
+    ///
+    /// Use this before a burst of known size to avoid having payloads returned-to-sender. The
+    /// receivers could be given work by another handle before you get to them.
+    pub fn wait_for_ready(&mut self, n: usize, timeout: Option<Duration>) -> usize {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let ready = self.registry.ready.clone();
+        let mut guard = ready.lock.lock().unwrap();
+        // As in `SharedSender::send_blocking`, register before looking at the states
+        ready.waiters.fetch_add(1, Ordering::SeqCst);
+        let count = loop {
+            self.refresh();
+            let count = self.workers.iter().filter(|w| w.state() == Ok(WorkerState::Waiting)).count();
+            if count >= n { break count; }
+            guard = match deadline {
+                None => ready.cond.wait(guard).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline { break count; }
+                    ready.cond.wait_timeout(guard, deadline - now).unwrap().0
+                }
+            };
+        };
+        ready.waiters.fetch_sub(1, Ordering::SeqCst);
+        count
+    }
+
+    /// Call `f` every time one of the receivers finishes its work and starts waiting for more,
+    /// passing it the receiver's `id`. This replaces any previous callback.
+    ///
+    /// `f` runs on the receiver's thread, just before it blocks, so it should be quick. It's
+    /// also called when a receiver first calls `recv`.
+    pub fn on_worker_ready<F>(&mut self, f: F) where F: Fn(usize) + Send + Sync + 'static {
+        let ready = &self.registry.ready;
+        *ready.callback.lock().unwrap() = Some(Arc::new(f));
+        ready.has_callback.store(true, Ordering::SeqCst);
+    }
+
+    /// Make the receivers process payloads strictly in the order they were enqueued.
+    ///
+    /// Each payload sent while this is on gets a sequence number. A receiver which is given one
+    /// doesn't return it from `recv` until the previous one has been finished, which happens
+    /// when the receiver which got it calls `recv` again (or is dropped). Payloads which are
+    /// taken back, or which expire, don't hold anything up.
+    ///
+    /// This serialises the work, so you lose most of the point of having several receivers,
+    /// but it does mean they all keep running in between. Shared between `try_clone` handles.
+    pub fn set_ordered(&mut self, on: bool) {
+        self.ordered = on;
+    }
+
+    /// Start (or stop) stamping payloads with the time they were enqueued, so that receivers can
+    /// measure how long they spent waiting. See `Receiver::latency`.
+    pub fn set_timestamps(&mut self, on: bool) {
+        self.timestamps = on;
+    }
+
+    /// The total latencies (queueing plus processing, in nanoseconds) of all the payloads sent
+    /// in timestamp mode which receivers have finished with. Shared between `try_clone` handles.
+    ///
+    /// This is a snapshot: it won't include payloads finished with after you call it. Use
+    /// `hdrhistogram::serialization` to send it elsewhere, and `Histogram::add` to merge
+    /// histograms from several hosts.
+    #[cfg(feature = "hdr")]
+    pub fn latency_histogram(&self) -> hdrhistogram::Histogram<u64> {
+        self.registry.latencies.lock().unwrap().clone()
+    }
+
+    /// Start (or stop) recording which receivers are sent to and when they're woken.
+    ///
+    /// Turning tracing off discards anything which hasn't been collected with `take_trace`.
+    pub fn set_tracing(&mut self, on: bool) {
+        self.trace = if on { Some(self.trace.take().unwrap_or_default()) } else { None };
+    }
+
+    /// Collect the events recorded since the last call, oldest first.
+    ///
+    /// Returns nothing unless tracing was turned on with `set_tracing`.
+    pub fn take_trace(&mut self) -> Vec<TraceEvent> {
+        self.trace.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    /// Zero the statistics, returning their values from just before.
+    pub fn reset_counters(&mut self) -> Counters {
+        mem::take(&mut self.counters)
+    }
+
+    /// Wake up *all* reciever threads.
+    ///
+    /// This function is guaranteed to wake up all the threads. If some threads are already
+    /// running, then those threads, or others, may be woken up spuriously in the future as a
+    /// result.
+    ///
+    /// This function does not block. It makes a single syscall, unless nothing has been enqueued
+    /// since the last call (or it's all been taken back with `drain_unwoken`/`cancel`), in which
+    /// case it returns straight away.
+    pub fn wake_all(&mut self) {
+        self.try_wake_all().unwrap();
+    }
+
+    /// Like `wake_all`, but returns an error if the syscall fails.
+    ///
+    /// If the eventfd's counter would overflow, writing to it blocks. When the sender was created
+    /// with `nonblock` set, you get an error of kind `WouldBlock` instead, and the receivers are
+    /// left unwoken; you can try again later.
+    pub fn try_wake_all(&mut self) -> io::Result<()> {
+        // Receivers which took their payload early are blocked reading the eventfd, and are
+        // counted here, so it's only safe to skip the write when this is zero.
+        if self.workers_to_unblock != 0 {
+            self.counters.wake_syscalls += 1;
+            self.eventfd.write(self.workers_to_unblock)?;
+        }
+        self.woken();
+        Ok(())
+    }
+
+    /// Start a batch of enqueues. The receivers are woken when the batch is committed or
+    /// dropped, so you can't forget to call `wake_all`.
+    ///
+    /// ```
+    /// # use burst_pool::*;
+    /// let mut sender: Sender<u32> = Sender::new();
+    /// let mut batch = sender.batch();
+    /// batch.enqueue(Box::new(1));  // no receivers, so this comes straight back
+    /// batch.commit().unwrap();
+    /// ```
+    pub fn batch(&mut self) -> Batch<'_, T> {
+        Batch { sender: self, committed: false }
+    }
+
+    /// The rest of `try_wake_all`, once the eventfd has been written to: wake the receivers with
+    /// their own `WaitStrategy`, and start a new generation.
+    fn woken(&mut self) {
+        let notified = !self.to_notify.is_empty();
+        for (waiter, n) in self.to_notify.drain(..) { waiter.notify(n); }
+        if self.workers_to_unblock == 0 && !notified { return; }
+        if self.workers_to_unblock != 0 {
+            if let Some(ref mut trace) = self.trace {
+                trace.push(TraceEvent::Wake { at: Instant::now(), receivers: self.workers_to_unblock as u64 });
+            }
+        }
+        self.workers_to_unblock = 0;
+        self.registry.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Reset the eventfd's counter to zero, returning the amount which was there.
+    ///
+    /// At a quiescent point (every receiver is idle and every wakeup has been consumed) this
+    /// should return zero; anything else indicates that the accounting has drifted.
+    ///
+    /// This must only be called when no receivers are blocked in `recv`, or it will steal their
+    /// wakeups. It doesn't block, but it makes at least two syscalls.
+    pub fn drain_eventfd(&mut self) -> io::Result<u64> {
+        let mut drained = 0;
+        while self.eventfd.is_readable()? {
+            drained += self.eventfd.read()?;
+        }
+        Ok(drained)
+    }
+
+    /// The value the eventfd's counter should have: everything written to it, less everything
+    /// the receivers (and `drain_eventfd`) have read back.
+    ///
+    /// Reading the counter itself would consume it, so this is tracked separately. Once every
+    /// receiver is idle, it should be zero; anything else means that a wakeup has been lost or
+    /// duplicated. (Receivers made with `Receiver::from_raw_fd` aren't counted.)
+    pub fn eventfd_balance(&self) -> i64 {
+        self.eventfd.balance()
+    }
+
+    /// Check that the receivers' states are consistent with their slots and with the eventfd.
+    /// Only available in debug builds; meant for tests and fuzzing.
+    ///
+    /// This is safe to call while receivers are running, but not while another handle to the
+    /// same receivers (see `try_clone`) is enqueueing. The eventfd check assumes it isn't shared
+    /// (see `reuse_eventfd`), and that no receiver has been removed since it last received.
+    #[cfg(debug_assertions)]
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let mut busy = 0;
+        for (i, w) in self.workers.iter().enumerate() {
+            let slot = w.slot.load(Ordering::SeqCst);
+            match w.state() {
+                Ok(WorkerState::Pending) if slot.is_null() =>
+                    return Err(InvariantViolation::PendingWithoutPayload { receiver: i }),
+                Ok(WorkerState::Waiting) if !slot.is_null() =>
+                    return Err(InvariantViolation::WaitingWithPayload { receiver: i }),
+                Ok(WorkerState::Waiting) => {}
+                Ok(_) => busy += 1,
+                Err(state) => return Err(InvariantViolation::BadState { receiver: i, state }),
+            }
+        }
+        // Each unit in the counter is waiting to be taken by a receiver which has been woken
+        // (or a receiver stealing its payload), and which therefore isn't WAITING.
+        let balance = self.eventfd.balance();
+        if balance < 0 || balance > busy as i64 {
+            return Err(InvariantViolation::EventFdBalance { balance, busy });
+        }
+        Ok(())
+    }
+
+    /// Take back all the payloads which have been enqueued since the last call to `wake_all`.
+    ///
+    /// The receivers which were holding these payloads go back to waiting, as if `enqueue` had
+    /// never been called. Payloads which a receiver has already taken (which can happen before
+    /// `wake_all` if it was woken up for some other reason) are not returned.
+    ///
+    /// This function does not block or make any syscalls.
+    pub fn drain_unwoken(&mut self) -> Vec<Box<T>> {
+        self.refresh();
+        let generation = self.registry.generation.load(Ordering::SeqCst);
+        let mut xs = vec![];
+        for w in self.workers.iter() {
+            if w.generation.load(Ordering::SeqCst) != generation { continue; }
+            // If the receiver beats us to it, it's taken the payload and will account for it
+            if w.cas_state(WorkerState::Pending, WorkerState::Waiting) == Ok(WorkerState::Pending) {
+                let x = unsafe { w.slot_take_box() };
+                xs.push(x.expect("drain_unwoken: slot contains null ptr. Please report this error."));
+                w.abandon(&self.registry.sequencer);
+                if w.waiter.is_none() { self.workers_to_unblock -= 1; }
+            }
+        }
+        xs
+    }
+
+    /// Take back every payload which no receiver has started on yet, whether or not it's been
+    /// woken.
+    ///
+    /// This is like `drain_unwoken`, but also covers payloads from earlier bursts whose receivers
+    /// haven't got round to them. Those receivers' wakeups are taken back out of the eventfd, so
+    /// they carry on sleeping rather than waking up to an empty slot.
+    ///
+    /// This function doesn't block, but it makes a syscall for each woken payload it takes back.
+    pub fn cancel_burst(&mut self) -> Vec<Box<T>> {
+        self.refresh();
+        let generation = self.registry.generation.load(Ordering::SeqCst);
+        let mut xs = vec![];
+        let mut woken = 0;
+        for w in self.workers.iter() {
+            // As in `drain_unwoken`, if the receiver beats us to it then it's all its own
+            if w.cas_state(WorkerState::Pending, WorkerState::Waiting) != Ok(WorkerState::Pending) {
+                continue;
+            }
+            let x = unsafe { w.slot_take_box() };
+            xs.push(x.expect("cancel_burst: slot contains null ptr. Please report this error."));
+            w.abandon(&self.registry.sequencer);
+            if w.waiter.is_some() { continue; }
+            if w.generation.load(Ordering::SeqCst) == generation {
+                self.workers_to_unblock -= 1;
+            } else {
+                woken += 1;
+            }
+        }
+        self.unwake(woken);
+        xs
+    }
+
+    /// Take back `woken` wakeups from the eventfd, for payloads which have been taken back.
+    ///
+    /// Nobody else can take these wakeups: receivers only read from the eventfd after claiming
+    /// their own payload.
+    fn unwake(&mut self, mut woken: u64) {
+        while woken > 0 {
+            let n = self.eventfd.read().expect("unwake: couldn't read the eventfd");
+            if n > woken {
+                // Counter mode: we took everyone's
+                self.eventfd.write((n - woken) as i64).expect("unwake: couldn't write the eventfd");
+                woken = 0;
+            } else {
+                woken -= n;
+            }
+        }
+    }
+
+    /// Forget the receivers which have been dropped, and take back any payloads they were given
+    /// but never took.
+    ///
+    /// Dropped receivers are skipped over by `enqueue`, but they stay on the list until this is
+    /// called, which costs memory and makes `enqueue` slower after the receivers from a spike in
+    /// load have gone. Receiver indices (as used by `set_weight` and `set_next_worker`) shift
+    /// down to fill the gaps.
+    ///
+    /// This makes a syscall for each payload it takes back which had already been woken.
+    pub fn compact(&mut self) -> Vec<Box<T>> {
+        self.refresh();
+        let generation = self.registry.generation.load(Ordering::SeqCst);
+        let dead: Vec<bool> = self.workers.iter().map(|w| w.state() == Ok(WorkerState::Dead)).collect();
+        if !dead.contains(&true) { return vec![]; }
+        let mut xs = vec![];
+        let mut woken = 0;
+        for w in self.workers.iter().zip(&dead).filter(|x| *x.1).map(|x| x.0) {
+            // Nobody will ever look at a dead receiver's slot again, so it's ours
+            match unsafe { w.slot_take_box() } {
+                Some(x) => xs.push(x),
+                None => continue,
+            }
+            w.abandon(&self.registry.sequencer);
+            if w.waiter.is_some() { continue; }
+            if w.generation.load(Ordering::SeqCst) == generation {
+                self.workers_to_unblock -= 1;
+            } else {
+                woken += 1;
+            }
+        }
+        self.unwake(woken);
+        {
+            // Our list is up to date, as long as no other handle is in use (see `try_clone`)
+            remove_dead(&mut self.registry.workers.lock().unwrap(), &dead);
+            self.registry.version.fetch_add(1, Ordering::SeqCst);
+        }
+        self.next_worker -= dead.iter().take(self.next_worker).filter(|&&d| d).count();
+        remove_dead(&mut self.weights, &dead);
+        remove_dead(&mut self.deficits, &dead);
+        self.refresh();
+        if self.next_worker >= self.workers.len() { self.next_worker = 0; }
+        xs
+    }
+
+    /// Take back a single payload which was sent with `enqueue_with_token`.
+    ///
+    /// This only succeeds if the payload hasn't been woken yet, ie. if `wake_all` hasn't been
+    /// called since it was enqueued, and its receiver hasn't taken it for some other reason. In
+    /// that case the receiver goes back to waiting, as if `enqueue` had never been called.
+    /// Otherwise you get `None`.
+    ///
+    /// This function does not block or make any syscalls.
+    pub fn cancel(&mut self, token: EnqueueToken) -> Option<Box<T>> {
+        let w = match self.workers.get(token.worker) {
+            Some(w) if w.id == token.id => w,
+            // The receivers have been moved around since
+            _ => self.workers.iter().find(|w| w.id == token.id)?,
+        };
+        if w.generation.load(Ordering::SeqCst) != self.registry.generation.load(Ordering::SeqCst) {
+            return None;
+        }
+        // While the receiver is PENDING, the slot still holds the payload we gave it last; if
+        // that's not the one the token refers to, ours has already been received.
+        if w.ticket.load(Ordering::SeqCst) != token.ticket { return None; }
+        if w.cas_state(WorkerState::Pending, WorkerState::Waiting) != Ok(WorkerState::Pending) {
+            return None;
+        }
+        let x = unsafe { w.slot_take_box() }.expect("cancel: slot contains null ptr. Please report this error.");
+        if w.waiter.is_none() { self.workers_to_unblock -= 1; }
+        w.abandon(&self.registry.sequencer);
+        Some(x)
+    }
+
+    /// Start a new burst. The token lets you wait until every payload which this handle hands
+    /// to a receiver from now on (until the next `begin_burst` or `end_burst`) has been finished
+    /// with.
+    ///
+    /// A receiver has finished with a payload when it next calls `recv` (or one of its
+    /// variants), or when it's dropped. Payloads which are taken back (eg. by `drain_unwoken`),
+    /// or which are discarded because the receiver was orphaned or the deadline passed, count as
+    /// finished too. Payloads which are returned-to-sender were never part of the burst.
+    ///
+    /// The token's count can reach zero while you're still enqueueing, if the receivers are
+    /// quick, so only wait on it once the burst has been sent.
+    pub fn begin_burst(&mut self) -> BurstToken {
+        let burst = Arc::new(BurstCounter::default());
+        self.burst = Some(burst.clone());
+        BurstToken(burst)
+    }
+
+    /// Stop adding payloads to the burst started by `begin_burst`.
+    pub fn end_burst(&mut self) {
+        self.burst = None;
+    }
+
+    /// Take back the payloads held by receivers which were orphaned before they could start on
+    /// them.
+    ///
+    /// Orphaned receivers never look at their slot again, so without this the payloads would be
+    /// lost. Receivers removed with `remove_receiver` give their payload back straight away, so
+    /// this is for receivers orphaned by other means (eg. by some other process sharing the
+    /// worker).
+    ///
+    /// This function does not block or make any syscalls.
+    pub fn reap_orphaned(&mut self) -> Vec<Box<T>> {
+        self.refresh();
+        let mut xs = vec![];
+        for w in self.workers.iter() {
+            if w.state() != Ok(WorkerState::Orphaned) { continue; }
+            match unsafe { w.slot_take_box() } {
+                Some(x) => xs.push(x),
+                None => continue,
+            }
+            w.abandon(&self.registry.sequencer);
+        }
+        xs
+    }
+}
+
+impl<T: Send> Sender<T> {
+    /// Attempt to send a payload to a waiting receiver.
+    ///
+    /// `enqueue` will only succeed if there is a receiver ready to take the value *right now*. If no
+    /// receivers are ready, the value is returned-to-sender.
+    ///
+    /// The receiver which accepted the previous payload is tried first; if it's busy, the rest
+    /// are tried round-robin.
+    ///
+    /// Note: `enqueue` will **not** unblock the receiver it sends the payload to. You must call
+    /// `wake_all` after calling `enqueue`!
+    ///
+    /// This function does not block or make any syscalls.
+    pub fn enqueue(&mut self, x: Box<T>) -> Option<Box<T>> {
+        self.place(x, None).err()
+    }
+
+    /// Like `enqueue`, but the payload will be discarded if it hasn't been received by `deadline`.
+    ///
+    /// The deadline is checked by the receiver when it wakes up. If it has passed, the payload
+    /// is dropped without being returned from `recv`, and the receiver's `expired` count is
+    /// incremented.
+    pub fn enqueue_deadline(&mut self, x: Box<T>, deadline: Instant) -> Option<Box<T>> {
+        self.place(x, Some(deadline)).err()
+    }
+
+    /// Like `enqueue`, but the payload carries a hop budget, which the receiver gets back from
+    /// `recv_with_ttl`.
+    ///
+    /// This is for receivers which send more work to the same pool: they pass on `ttl - 1`, and
+    /// once the budget reaches zero this refuses the payload, so the amount of work one payload
+    /// can generate is bounded. You get the payload back if `ttl` is zero or if no receiver is
+    /// ready.
+    pub fn enqueue_ttl(&mut self, x: Box<T>, ttl: u8) -> Option<Box<T>> {
+        if ttl == 0 { return Some(x); }
+        match self.find_worker() {
+            Some(i) => {
+                self.fill_ttl(i, Box::into_raw(x), None, Some(ttl));
+                None
+            }
+            None => {
+                self.counters.dropped += 1;
+                Some(x)
+            }
+        }
+    }
+
+    /// Like `enqueue`, but if no receiver is ready, keep trying for up to `spin` more iterations.
+    ///
+    /// This catches the case where a receiver is just about to finish its previous piece of work.
+    /// It busy-waits, so it's only appropriate for small values of `spin`; `enqueue_spin(x, 0)`
+    /// is the same as `enqueue(x)`.
+    ///
+    /// This function does not make any syscalls.
+    pub fn enqueue_spin(&mut self, x: Box<T>, spin: u32) -> Option<Box<T>> {
+        for _ in 0..spin {
+            if let Some(i) = self.find_worker() {
+                self.fill(i, Box::into_raw(x), None);
+                return None;
+            }
+            hint::spin_loop();
+        }
+        self.place(x, None).err()
+    }
+
+    /// Like `enqueue` followed by `wake_all`, but if no receiver is ready, wait asynchronously
+    /// until one is.
+    ///
+    /// The returned future is woken whenever a receiver finishes its work, at which point it
+    /// tries again. Dropping it before it completes gives up on sending `x`.
+    pub fn enqueue_async<'a>(&'a mut self, x: Box<T>) -> EnqueueAsync<'a, T> {
+        EnqueueAsync { sender: self, item: Some(x), registered: false }
+    }
+
+    /// Hold on to a payload, to be sent by a later call to `flush_staged`.
+    ///
+    /// This is for producing work before there are any receivers to take it, eg. at start-up.
+    /// The staged payloads belong to this handle, and are dropped with it.
+    pub fn stage(&mut self, x: Box<T>) {
+        self.staged.push_back(x);
+    }
+
+    /// Send as many staged payloads as there are waiting receivers, in the order they were
+    /// staged, and wake the receivers. Returns the number of payloads which are still staged.
+    pub fn flush_staged(&mut self) -> usize {
+        while !self.staged.is_empty() {
+            match self.find_worker() {
+                Some(i) => {
+                    let x = self.staged.pop_front().unwrap();
+                    self.fill(i, Box::into_raw(x), None);
+                }
+                None => break,
+            }
+        }
+        self.wake_all();
+        self.staged.len()
+    }
+
+    /// Like `enqueue`, but if no receiver is ready, put the payload in a shared queue instead.
+    ///
+    /// Receivers check the queue whenever they come back to `recv`, before waiting, so spilled
+    /// payloads are picked up by whichever receiver finishes its work first. They're taken in
+    /// the order they were spilled, and each call to this function first moves as many spilled
+    /// payloads as it can to receivers which have become ready in the meantime. (A payload can
+    /// therefore linger in the queue if a receiver finishes just as it's spilled, and nothing
+    /// else is sent.)
+    ///
+    /// The queue holds up to `capacity` payloads (see `set_spill_capacity`; by default it's
+    /// zero). Once it's full, you get the payload back.
+    pub fn enqueue_or_spill(&mut self, x: Box<T>) -> Option<Box<T>> {
+        let spill = self.registry.spill.clone();
+        let mut queue = spill.queue.lock().unwrap();
+        queue.push_back(x);
+        while !queue.is_empty() {
+            match self.find_worker() {
+                Some(i) => {
+                    let y = queue.pop_front().unwrap();
+                    self.fill(i, Box::into_raw(y), None);
+                }
+                None => break,
+            }
+        }
+        // If it's over capacity, then `x` is still at the back
+        let ret = if queue.len() > self.spill_capacity {
+            self.counters.dropped += 1;
+            queue.pop_back()
+        } else {
+            None
+        };
+        spill.len.store(queue.len(), Ordering::SeqCst);
+        ret
+    }
+
+    /// Set the number of payloads which `enqueue_or_spill` can leave in the shared queue.
+    pub fn set_spill_capacity(&mut self, capacity: usize) {
+        self.spill_capacity = capacity;
+    }
+
+    /// Like `enqueue`, but only send to a receiver whose tag satisfies `pred`.
+    ///
+    /// Receivers which weren't made by `mk_receiver_tagged`, or whose tag isn't a `Tag`, never
+    /// match. The matching receivers are tried round-robin, as usual (weights are ignored).
+    pub fn enqueue_matching<Tag, F>(&mut self, x: Box<T>, pred: F) -> Option<Box<T>>
+    where Tag: Any, F: Fn(&Tag) -> bool
+    {
+        self.refresh();
+        for i in 0..self.workers.len() {
+            let i2 = (i + self.next_worker) % self.workers.len();
+            let matches = self.workers[i2].tag.as_ref()
+                .and_then(|tag| tag.downcast_ref())
+                .is_some_and(&pred);
+            if matches && self.claim(i2) {
+                self.fill(i2, Box::into_raw(x), None);
+                return None;
+            }
+        }
+        self.counters.dropped += 1;
+        Some(x)
+    }
+
+    /// Like `enqueue`, but on success returns a token which can be passed to `cancel` to take the
+    /// payload back, so long as it hasn't been woken yet.
+    pub fn enqueue_with_token(&mut self, x: Box<T>) -> Result<EnqueueToken, Box<T>> {
+        let worker = self.place(x, None)?;
+        let w = &self.workers[worker];
+        Ok(EnqueueToken { worker, id: w.id, ticket: w.ticket.load(Ordering::SeqCst) })
+    }
+
+    /// Like `enqueue`, but takes the payload by value.
+    ///
+    /// If `T` fits in `INLINE_WORDS` words it's stored directly in the receiver, so nothing is
+    /// allocated; pair this with `Receiver::recv_value`. Larger payloads are boxed, but only once
+    /// a receiver has been found: if none is ready, you get the value straight back.
+    ///
+    /// Payloads sent this way can be received with either `recv` or `recv_value`.
+    pub fn enqueue_value(&mut self, x: T) -> Option<T> {
+        self.place_value(x).err()
+    }
+
+    /// Like `enqueue`, but if an equal payload is already waiting to be woken, the new one is
+    /// dropped (ie. returned) and nothing is sent.
+    ///
+    /// This suits idempotent "refresh" signals, where a second copy would be wasted work. Only
+    /// payloads enqueued since the last `wake_all` are compared against.
+    ///
+    /// This function does not block or make any syscalls.
+    pub fn enqueue_coalesced(&mut self, x: Box<T>) -> Option<Box<T>> where T: PartialEq {
+        self.refresh();
+        let generation = self.registry.generation.load(Ordering::SeqCst);
+        for w in self.workers.iter() {
+            if w.generation.load(Ordering::SeqCst) != generation { continue; }
+            // Stop the receiver taking the payload while we look at it. If it's not PENDING,
+            // there's nothing to compare against.
+            if w.cas_state(WorkerState::Pending, WorkerState::Locked) != Ok(WorkerState::Pending) {
+                continue;
+            }
+            let ptr = w.slot.load(Ordering::SeqCst);
+            // A payload which is still to be made can't be compared
+            let equal = ptr != w.maker_ptr() && unsafe { *ptr == *x };
+            // The receiver may have been dropped meanwhile, in which case it stays DEAD
+            let _ = w.cas_state(WorkerState::Locked, WorkerState::Pending);
+            if equal { return Some(x); }
+        }
+        self.enqueue(x)
+    }
+
+    /// Like `enqueue`, but instead of a payload, send a function which makes it.
+    ///
+    /// The receiver calls `make` on its own thread, before returning the payload from `recv`. This
+    /// moves the cost of constructing the payload off the sending thread, and lets it use the
+    /// receiver's thread-local state. If no receiver is ready, you get `make` back. (If the
+    /// payload is taken back with `drain_unwoken` or `cancel`, `make` is called on the sending
+    /// thread.)
+    pub fn enqueue_fn<F>(&mut self, make: F) -> Option<F> where F: FnOnce() -> T + Send + 'static {
+        match self.find_worker() {
+            Some(i) => {
+                // We own the maker for the same reason we own the deadline.
+                let w = &self.workers[i];
+                unsafe { *w.maker.get() = Some(Box::new(make)); }
+                let ptr = w.maker_ptr();
+                self.fill(i, ptr, None);
+                None
+            }
+            None => {
+                self.counters.dropped += 1;
+                Some(make)
+            }
+        }
+    }
+
+    /// Attempt to send several payloads at once, reporting where each one ended up.
+    ///
+    /// Payloads are placed in order, exactly as if by repeated calls to `enqueue`. Once a payload
+    /// fails to find a waiting receiver all the remaining payloads are returned unplaced, without
+    /// scanning the receivers again.
+    ///
+    /// As with `enqueue`, you must call `wake_all` afterwards. This function does not block or
+    /// make any syscalls.
+    pub fn enqueue_batch(&mut self, xs: Vec<Box<T>>) -> BatchResult<T> {
+        let mut placements = Vec::with_capacity(xs.len());
+        let mut full = false;
+        for x in xs {
+            if full {
+                self.counters.dropped += 1;
+                placements.push(Placement::Unplaced(x));
+                continue;
+            }
+            match self.place(x, None) {
+                Ok(i) => placements.push(Placement::Placed(i)),
+                Err(x) => {
+                    full = true;
+                    placements.push(Placement::Unplaced(x));
+                }
+            }
+        }
+        BatchResult { placements }
+    }
+
+    /// Send `count` bursts, one every `period`, each made by calling `items` and then enqueuing
+    /// and waking everything it returns. Returns the payloads which couldn't be placed.
+    ///
+    /// Burst `n` is sent at `n * period` after the first one, however long `items` and the
+    /// sending take, so the cadence doesn't drift. If a burst is late, the next one goes
+    /// straight away. This blocks until the last burst has been sent.
+    ///
+    /// If `period` is so long that a burst's time can't be represented as an `Instant`, each of
+    /// the remaining bursts is sent `period` after the previous one instead.
+    pub fn drive<F>(&mut self, mut items: F, period: Duration, count: usize) -> Vec<Box<T>>
+    where F: FnMut() -> Vec<Box<T>> {
+        let mut due = Some(Instant::now());
+        let mut unplaced = vec![];
+        for _ in 0..count {
+            match due {
+                Some(due) => {
+                    let now = Instant::now();
+                    if due > now { thread::sleep(due - now); }
+                }
+                None => thread::sleep(period),
+            }
+            unplaced.extend(self.enqueue_batch(items()).into_unplaced());
+            self.wake_all();
+            due = due.and_then(|t| t.checked_add(period));
+        }
+        unplaced
+    }
+
+    /// On success, returns the index of the worker which the payload was placed with.
+    fn place(&mut self, x: Box<T>, deadline: Option<Instant>) -> Result<usize, Box<T>> {
+        match self.find_worker() {
+            Some(i) => {
+                self.fill(i, Box::into_raw(x), deadline);
+                Ok(i)
+            }
+            None => {
+                self.counters.dropped += 1;
+                Err(x)
+            }
+        }
+    }
+
+    /// Like `place`, but stores the payload in the worker's inline buffer if it fits.
+    fn place_value(&mut self, x: T) -> Result<usize, T> {
+        match self.find_worker() {
+            Some(i) => {
+                let ptr = if Worker::<T>::fits_inline() {
+                    // We own the inline buffer for the same reason we own the deadline.
+                    let ptr = self.workers[i].inline_ptr();
+                    unsafe { ptr::write(ptr, x); }
+                    ptr
+                } else {
+                    Box::into_raw(Box::new(x))
+                };
+                self.fill(i, ptr, None);
+                Ok(i)
+            }
+            None => {
+                self.counters.dropped += 1;
+                Err(x)
+            }
+        }
+    }
+
+    /// Find a receiver in WAITING state and claim it (see `claim`).
+    fn find_worker(&mut self) -> Option<usize> {
+        self.refresh();
+        if self.selector.is_some() { return self.find_worker_selected(); }
+        if !self.weights.is_empty() { return self.find_worker_weighted(); }
+        // Under light load, the worker we sent to last time has probably finished by now.
+        let mut target_worker = self.last_worker.filter(|&i| self.claim(i));
+        if target_worker.is_none() {
+            for i in 0..self.workers.len() {
+                let i2 = (i + self.next_worker) % self.workers.len();
+                if Some(i2) == self.last_worker { continue; /* we just tried it */ }
+                if self.claim(i2) {
+                    target_worker = Some(i2);
+                    break;
+                }
+            }
+        }
+        target_worker
+    }
+
+    /// Ask the user's selector which receiver to use.
+    fn find_worker_selected(&mut self) -> Option<usize> {
+        let mut states = mem::take(&mut self.selector_states);
+        let ret = loop {
+            states.clear();
+            // A receiver in an invalid state is treated as busy, as in `claim`
+            states.extend(self.workers.iter().map(|w| w.state().unwrap_or(WorkerState::Locked)));
+            let i = match (self.selector.as_mut().unwrap())(&states) {
+                Some(i) if i < states.len() => i,
+                _ => break None,
+            };
+            if self.claim(i) { break Some(i); }
+            if states[i] != WorkerState::Waiting { break None; /* it chose a busy receiver */ }
+            // It stopped waiting after we looked; try again
+        };
+        self.selector_states = states;
+        ret
+    }
+
+    /// Smooth weighted round-robin: each waiting receiver earns its weight in credit, and the
+    /// one with the most credit is chosen and pays for it with the total weight.
+    fn find_worker_weighted(&mut self) -> Option<usize> {
+        let n = self.workers.len();
+        self.deficits.resize(n, 0);
+        loop {
+            let mut total = 0;
+            let mut best: Option<usize> = None;
+            for i in 0..n {
+                if self.workers[i].state() != Ok(WorkerState::Waiting) { continue; }
+                let weight = i64::from(self.weights.get(i).cloned().unwrap_or(1));
+                self.deficits[i] += weight;
+                total += weight;
+                if best.is_none_or(|b| self.deficits[i] > self.deficits[b]) { best = Some(i); }
+            }
+            let i = best?;
+            self.deficits[i] -= total;
+            if self.claim(i) { return Some(i); }
+            // It stopped waiting after we looked; try again
+        }
+    }
+
+    /// Write a payload to the slot of a worker we've just claimed, and note that we need to
+    /// increment the eventfd.
+    fn fill(&mut self, i: usize, x: *mut T, deadline: Option<Instant>) {
+        self.fill_ttl(i, x, deadline, None)
+    }
+
+    /// Like `fill`, but the payload has a hop budget.
+    fn fill_ttl(&mut self, i: usize, x: *mut T, deadline: Option<Instant>, ttl: Option<u8>) {
+        // We claimed the worker by locking it, so the slot is ours until we publish it as
+        // PENDING at the end. Until then neither the receiver nor a thief will touch it.
+        unsafe {
+            *self.workers[i].deadline.get() = deadline;
+            *self.workers[i].ttl.get() = ttl;
+        }
+        // Likewise, nobody else writes these, and the state change below publishes them, so
+        // they don't need ordering of their own. This is the hot path: keep it cheap.
+        let w = &self.workers[i];
+        w.generation.store(self.registry.generation.load(Ordering::Relaxed), Ordering::Relaxed);
+        w.ticket.store(w.ticket.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+        if self.ordered {
+            w.seq.store(self.registry.sequencer.stamp(), Ordering::Relaxed);
+        } else {
+            // Whoever emptied the slot last reset it
+            debug_assert_eq!(w.seq.load(Ordering::Relaxed), NO_SEQ);
+        }
+        if let Some(ref burst) = self.burst {
+            burst.add();
+            unsafe { *self.workers[i].burst.get() = Some(burst.clone()); }
+        }
+        if self.timestamps {
+            unsafe { *self.workers[i].enqueued_at.get() = Some(Instant::now()); }
+        }
+        assert!(self.workers[i].slot_put(x).is_ok(), "enqueue: slot contains non-null ptr. Please report this error.");
+        // The receiver may have been dropped meanwhile, in which case it stays DEAD
+        let _ = self.workers[i].cas_state(WorkerState::Locked, WorkerState::Pending);
+        self.next_worker = (i + 1) % self.workers.len();
+        self.last_worker = Some(i);
+        match self.workers[i].waiter {
+            None => self.workers_to_unblock += 1,
+            Some(ref waiter) => match self.to_notify.iter_mut().find(|e| Arc::ptr_eq(&e.0, waiter)) {
+                Some(e) => e.1 += 1,
+                None => self.to_notify.push((waiter.clone(), 1)),
+            },
+        }
+        self.counters.sent += 1;
+        if let Some(ref mut trace) = self.trace {
+            trace.push(TraceEvent::Enqueue { at: Instant::now(), receiver: i });
+        }
+    }
+
+    /// Try to move a worker from WAITING to LOCKED, so that we can fill its slot. `fill` moves
+    /// it on to PENDING once the payload is in place.
+    fn claim(&mut self, i: usize) -> bool {
+        self.counters.cas_attempts += 1;
+        match self.workers[i].cas_state(WorkerState::Waiting, WorkerState::Locked) {
+            Ok(WorkerState::Waiting) => true, /* it was ready */
+            Ok(WorkerState::Pending) | Ok(WorkerState::Running) | Ok(WorkerState::Locked) =>
+                false, /* it's busy */
+            Ok(WorkerState::Orphaned) => false, /* it's been removed, and we haven't noticed yet */
+            Ok(WorkerState::Dead) => false, /* it's been dropped, and hasn't been compacted yet */
+            x => {
+                bad_state(format_args!("enqueue: bad state ({}). Please report this error.", raw_state(x)));
+                false /* treat it as busy */
+            }
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    fn new(
+        worker: Arc<Worker<T>>,
+        eventfd: Arc<EventFd>,
+        ready: Arc<Readiness>,
+        sequencer: Arc<Sequencer>,
+        spill: Arc<Spill<T>>,
+    ) -> Receiver<T> {
+        Receiver {
+            inner: worker,
+            ready,
+            #[cfg(feature = "epoll")]
+            epoll: Epoll::new(&eventfd),
+            eventfd,
+            expired: 0,
+            spurious_backoff: None,
+            yield_strategy: YieldStrategy::default(),
+            spin: None,
+            sequencer,
+            current_seq: NO_SEQ,
+            current_burst: None,
+            current_times: None,
+            latency: None,
+            generation: 0,
+            ttl: None,
+            spill,
+            history: None,
+            parked: false,
+            #[cfg(feature = "metrics")]
+            wakeup_latency: LatencyHistogram::default(),
+            #[cfg(feature = "hdr")]
+            latencies: None,
+        }
+    }
+
+    /// Create a receiver which waits on an eventfd you already have, for a worker you're sharing
+    /// with some other producer.
+    ///
+    /// This is a building block for putting the producer and consumers in different processes
+    /// (passing the eventfd with `SCM_RIGHTS`, or by inheritance).
+    ///
+    /// # Safety
+    ///
+    /// The receiver takes ownership of `fd`, and closes it when dropped. `fd` must be an eventfd
+    /// in semaphore mode. Whoever writes to it must follow the same protocol as `Sender`: only
+    /// move `worker` from waiting to pending, fill its slot with a pointer the receiver is able
+    /// to free before doing so, and write one to the eventfd for each payload. Nothing else
+    /// may read from the eventfd except receivers following this protocol. If `worker` lives in
+    /// memory shared between processes, the payloads must too, and must be freeable from this
+    /// process.
+    pub unsafe fn from_raw_fd(fd: RawFd, worker: Arc<Worker<T>>) -> Receiver<T> {
+        Receiver::new(worker, Arc::new(EventFd::new(fd, 0)), Arc::new(Readiness::new()), Arc::new(Sequencer::new()), Arc::new(Spill::new()))
+    }
+
+    /// Blocks until (1) a message is sent to this `Receiver`, and (2) wake_all() is called on the
+    /// associated `Sender`.
+    ///
+    /// Messages sent with `enqueue_deadline` whose deadline has passed are discarded, and `recv`
+    /// goes back to waiting.
+    pub fn recv(&mut self) -> Result<Box<T>, RecvError> {
+        self.recv_with(Worker::unpack_box)
+    }
+
+    /// Like `recv`, but also returns the number of the `wake_all` call which delivered the
+    /// payload.
+    ///
+    /// The sender's calls to `wake_all` are numbered from zero, across all its handles. Payloads
+    /// which were sent in the same burst get the same number.
+    pub fn recv_with_generation(&mut self) -> Result<(Box<T>, u64), RecvError> {
+        let x = self.recv()?;
+        Ok((x, self.generation))
+    }
+
+    /// Like `recv`, but also returns the payload's hop budget, if it was sent with
+    /// `Sender::enqueue_ttl`.
+    pub fn recv_with_ttl(&mut self) -> Result<(Box<T>, Option<u8>), RecvError> {
+        let x = self.recv()?;
+        Ok((x, self.ttl))
+    }
+
+    /// Like `recv`, but returns the payload by value.
+    ///
+    /// Payloads sent with `Sender::enqueue_value` can be received this way without touching the
+    /// allocator.
+    pub fn recv_value(&mut self) -> Result<T, RecvError> {
+        self.recv_with(Worker::unpack_value)
+    }
+
+    /// Like `recv_value`, but moves the payload into `buf`, dropping whatever was there.
+    ///
+    /// This lets a worker keep its working storage in one place, rather than managing a fresh
+    /// box for every payload.
+    pub fn recv_into(&mut self, buf: &mut T) -> Result<(), RecvError> {
+        *buf = self.recv_value()?;
+        Ok(())
+    }
+
+    /// Repeatedly `recv` payloads and pass them to `f`, for as long as `pred` returns true.
+    ///
+    /// `pred` is checked before waiting for each payload. Returns `Ok` once `pred` returns false,
+    /// or `Err` if the sender goes away.
+    pub fn recv_while<P, F>(&mut self, mut pred: P, mut f: F) -> Result<(), RecvError>
+    where P: FnMut() -> bool, F: FnMut(Box<T>)
+    {
+        while pred() {
+            f(self.recv()?);
+        }
+        Ok(())
+    }
+
+    fn recv_with<U>(&mut self, unpack: unsafe fn(&Worker<T>, *mut T) -> U) -> Result<U, RecvError> {
+        if let Some(x) = self.take_spilled() {
+            return Ok(unsafe { unpack(&self.inner, Box::into_raw(x)) });
+        }
+        loop {
+            let (ptr, deadline) = self.recv_inner()?;
+            let x = unsafe { unpack(&self.inner, ptr) };
+            if !self.expire(deadline) {
+                self.inner.processed.fetch_add(1, Ordering::Relaxed);
+                return Ok(x);
+            }
+        }
+    }
+
+    /// If the deadline of the payload we've just taken has passed, discard it: count it as
+    /// expired, and finish with it so that it doesn't hold up the payloads after it.
+    fn expire(&mut self, deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(deadline) if Instant::now() > deadline => {
+                self.expired += 1;
+                self.finish_payload();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The number of messages which this receiver has discarded because their deadline had
+    /// passed.
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    /// Limit how hard this receiver spins when it keeps being woken up for somebody else's work.
+    ///
+    /// Normally a receiver which wakes up and finds no work in its slot yields and goes straight
+    /// back to sleep. When there are more receivers than cores this can degenerate into a busy
+    /// loop. With a backoff set, after `max` consecutive spurious wakeups the receiver sleeps
+    /// for `sleep` before polling again.
+    pub fn set_spurious_backoff(&mut self, max: u32, sleep: Duration) {
+        self.spurious_backoff = Some((max, sleep));
+    }
+
+    /// The number of payloads this receiver has received.
+    pub fn processed(&self) -> u64 {
+        self.inner.processed.load(Ordering::Relaxed)
+    }
+
+    /// Choose what this receiver does after being woken up for somebody else's work.
+    ///
+    /// On some systems `sched_yield` is nearly a no-op, so the default strategy of yielding can
+    /// turn into a tight loop. A spurious backoff (see `set_spurious_backoff`) takes precedence
+    /// over this.
+    pub fn set_yield_strategy(&mut self, strategy: YieldStrategy) {
+        self.yield_strategy = strategy;
+    }
+
+    /// Before blocking, spin for up to `initial` iterations waiting for work to arrive.
+    ///
+    /// The number of iterations adapts to the workload: it goes down towards zero (ie. blocking
+    /// straight away) when spinning mostly doesn't find any work, and back up towards `initial`
+    /// when it does. Spinning avoids a `poll` when bursts arrive in quick succession, at the
+    /// cost of burning CPU in between.
+    pub fn adaptive_spin(&mut self, initial: u32) {
+        self.spin = Some(AdaptiveSpin::new(initial));
+    }
+
+    /// Keep a record of the last `n` states this receiver saw, and what it was doing at the time.
+    ///
+    /// The record is included in "bad state" errors, so that they say how the receiver got there.
+    pub fn with_history(mut self, n: usize) -> Receiver<T> {
+        self.history = Some(History { ops: VecDeque::with_capacity(n), len: n });
+        self
+    }
+
+    /// The states recorded since `with_history` was called, oldest first. Each is the state the
+    /// receiver was left in (or found itself in) by the operation.
+    pub fn history(&self) -> Vec<(Op, WorkerState)> {
+        self.history.as_ref().map_or(vec![], |h| h.ops.iter().cloned().collect())
+    }
+
+    fn record(&mut self, op: Op, state: WorkerState) {
+        if let Some(ref mut history) = self.history { history.record(op, state); }
+    }
+
+    /// The history, for appending to error messages.
+    fn history_note(&self) -> String {
+        match self.history {
+            Some(ref history) => format!(" History: {:?}", history.ops),
+            None => String::new(),
+        }
+    }
+
+    /// Where the time went for the last payload this receiver finished with, if it was sent in
+    /// timestamp mode (see `Sender::set_timestamps`).
+    ///
+    /// A payload is finished with when the receiver calls `done`, or next waits for work.
+    pub fn latency(&self) -> Option<LatencyBreakdown> {
+        self.latency
+    }
+
+    /// Say that you've finished with the payload you received last, without waiting for the
+    /// next one.
+    ///
+    /// This happens anyway when you next call `recv`. Doing it sooner lets the next payload go
+    /// ahead in ordered mode, completes the payload's burst (see `Sender::begin_burst`), and
+    /// makes its `latency` available.
+    pub fn done(&mut self) {
+        self.finish_payload();
+    }
+
+    /// How long this receiver has taken to get hold of its payload after being woken: from
+    /// `poll` returning to the payload being taken out of the slot.
+    ///
+    /// This is mostly the time spent waiting to be scheduled after the wakeup, plus the second
+    /// syscall. Spurious wakeups aren't counted.
+    #[cfg(feature = "metrics")]
+    pub fn wakeup_latency(&self) -> &LatencyHistogram {
+        &self.wakeup_latency
+    }
+
+    /// The number of iterations this receiver currently spins for before blocking. See
+    /// `adaptive_spin`.
+    pub fn spin_count(&self) -> u32 {
+        self.spin.map_or(0, |s| s.spin)
+    }
+
+    /// Run the calling thread (which should be the one that owns this receiver) under the
+    /// `SCHED_FIFO` real-time policy at priority `prio`.
+    ///
+    /// This can eliminate scheduler-induced tail latency, but a busy real-time thread can starve
+    /// the rest of the system, so use with care. It requires `CAP_SYS_NICE` (or a suitable
+    /// `RLIMIT_RTPRIO`); otherwise you get a `PermissionDenied` error.
+    pub fn set_realtime_priority(&self, prio: i32) -> io::Result<()> {
+        let param = libc::sched_param { sched_priority: prio };
+        match unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) } {
+            0 => Ok(()),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+
+    /// The state this receiver shares with its sender. Pass it to another receiver's
+    /// `steal_from` to let that receiver take work which was sent to this one.
+    pub fn worker(&self) -> Arc<Worker<T>> {
+        self.inner.clone()
+    }
+
+    /// Take the payload which has been sent to another receiver, if it hasn't started on it yet.
+    ///
+    /// `other` must belong to a receiver of the same sender (see `worker`); if it doesn't, you
+    /// get `None`. The other receiver carries on waiting, as if the payload had never been sent
+    /// to it. If the payload hasn't been woken yet, this blocks until `wake_all` is called, just
+    /// as `recv` would have.
+    /// Payloads whose deadline has passed are discarded, and count towards this receiver's
+    /// `expired`.
+    pub fn steal_from(&mut self, other: &Worker<T>) -> Option<Box<T>> {
+        // Stealing means we've finished with whatever we were doing before
+        self.finish_payload();
+        // Lock the other worker while we take the payload, because as soon as it's WAITING again
+        // the sender may reuse its inline buffer. Another sender's payload would come with a wakeup
+        // on another eventfd, and a sequence number from another sequencer.
+        if other.registry != self.inner.registry { return None; }
+        if other.cas_state(WorkerState::Pending, WorkerState::Locked) != Ok(WorkerState::Pending) {
+            return None;
+        }
+        let ptr = other.slot_take().expect("steal_from: slot contains null ptr. Please report this error.");
+        let deadline = unsafe { (*other.deadline.get()).take() };
+        self.current_burst = unsafe { (*other.burst.get()).take() };
+        self.stamp_received(unsafe { (*other.enqueued_at.get()).take() });
+        let seq = other.seq.swap(NO_SEQ, Ordering::SeqCst);
+        let x = unsafe { other.unpack_box(ptr) };
+        // If the sender orphaned it meanwhile, leave it orphaned
+        let _ = other.cas_state(WorkerState::Locked, WorkerState::Waiting);
+        // The wakeup for this payload is ours to consume now. The other receiver will see that
+        // it's WAITING and treat its wakeup as spurious.
+        if other.waiter.is_none() {
+            self.eventfd.read().unwrap();
+        }
+        self.start_seq(seq);
+        if self.expire(deadline) { return None; }
+        self.inner.processed.fetch_add(1, Ordering::Relaxed);
+        Some(x)
+    }
+
+    /// The tag this receiver was made with by `Sender::mk_receiver_tagged`, if it's a `Tag`.
+    pub fn tag<Tag: Any>(&self) -> Option<&Tag> {
+        self.inner.tag.as_ref().and_then(|tag| tag.downcast_ref())
+    }
+
+    /// A number which identifies this receiver, unique within the process.
+    ///
+    /// Useful for telling apart the errors from several receivers handled by one thread.
+    pub fn id(&self) -> usize {
+        self.inner.id
+    }
+
+    /// The total number of times this receiver has been woken up when there was no work for it.
+    pub fn spurious_wakeups(&self) -> u64 {
+        self.inner.spurious.load(Ordering::Relaxed)
+    }
+
+    /// Block until the eventfd is signalled, or until `timeout` has elapsed. Returns whether it
+    /// was signalled.
+    #[cfg(not(feature = "epoll"))]
+    fn wait(&self, timeout: Option<Duration>) -> bool {
+        // Block until eventfd becomes non-zero
+        sys_poll(self.eventfd.0, timeout_ms(timeout)).unwrap()
+    }
+
+    /// Block until the eventfd is signalled, or until `timeout` has elapsed. Returns whether it
+    /// was signalled.
+    #[cfg(feature = "epoll")]
+    fn wait(&self, timeout: Option<Duration>) -> bool {
+        // Block until eventfd is written to
+        self.epoll.wait(timeout_ms(timeout)).unwrap()
+    }
+
+    /// Like `recv`, but also returns if `extra_fd` becomes readable.
+    ///
+    /// You get `Interrupted::External` if `extra_fd` fired and there was no work for this
+    /// receiver. Nothing is read from `extra_fd`, so it's up to you to clear it. Any payload
+    /// which arrives meanwhile stays in the slot for the next call.
+    ///
+    /// # Panics
+    ///
+    /// If the receiver has its own `WaitStrategy` (see `Sender::mk_receiver_with_wait`).
+    pub fn recv_interruptible(&mut self, extra_fd: RawFd) -> Result<Interrupted<Box<T>>, RecvError> {
+        assert!(self.inner.waiter.is_none(), "recv_interruptible: receiver doesn't wait on the eventfd");
+        if let Some(x) = self.take_spilled() { return Ok(Interrupted::Work(x)); }
+        let mut consecutive_spurious = 0;
+        loop {
+            self.begin_wait()?;
+            let mut pollfds = [
+                libc::pollfd { fd: self.eventfd.0, events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: extra_fd, events: libc::POLLIN, revents: 0 },
+            ];
+            retry(|| unsafe { libc::poll(pollfds.as_mut_ptr(), 2, -1) as isize }).unwrap();
+            let external = pollfds[1].revents != 0;
+            if pollfds[0].revents != 0 {
+                // The wakeup may be for us, and if so we'd better take it
+                if let Some((ptr, deadline)) = self.take_wakeup()? {
+                    let x = unsafe { self.inner.unpack_box(ptr) };
+                    if !self.expire(deadline) {
+                        self.inner.processed.fetch_add(1, Ordering::Relaxed);
+                        return Ok(Interrupted::Work(x));
+                    }
+                    continue;
+                }
+                if !external { self.after_spurious(&mut consecutive_spurious); }
+            }
+            // We haven't touched the eventfd, so whatever the sender has given us (if anything)
+            // is still there for next time
+            if external { return Ok(Interrupted::External); }
+        }
+    }
+
+    /// Like `recv`, but if no work arrives for `idle`, run `maint` and carry on waiting.
+    ///
+    /// The receiver stays available for work while `maint` runs, so a payload which arrives
+    /// meanwhile is picked up as soon as it returns. Receivers with their own `WaitStrategy`
+    /// (see `Sender::mk_receiver_with_wait`) don't time out, so for them `maint` is never called.
+    pub fn recv_with_maintenance<F: FnMut()>(&mut self, idle: Duration, mut maint: F) -> Result<Box<T>, RecvError> {
+        if let Some(x) = self.take_spilled() { return Ok(x); }
+        let mut consecutive_spurious = 0;
+        loop {
+            self.begin_wait()?;
+            let woken = match self.inner.waiter.clone() {
+                Some(waiter) => self.wait_for_pending(&*waiter)?,
+                None if self.spin_for_work() || self.wait(Some(idle)) => self.take_wakeup()?,
+                None => {
+                    // Timed out. We're still WAITING (or PENDING, if the sender has just got to
+                    // us, in which case the wakeup will be waiting when we get back).
+                    consecutive_spurious = 0;
+                    maint();
+                    continue;
+                }
+            };
+            match woken {
+                Some((ptr, deadline)) => {
+                    let x = unsafe { self.inner.unpack_box(ptr) };
+                    if !self.expire(deadline) {
+                        self.inner.processed.fetch_add(1, Ordering::Relaxed);
+                        return Ok(x);
+                    }
+                }
+                None => self.after_spurious(&mut consecutive_spurious),
+            }
+        }
+    }
+
+    fn recv_inner(&mut self) -> Result<(*mut T, Option<Instant>), RecvError> {
+        // 1. Set state to WAITING
+        // 2. Block on eventfd
+        // 3. Check state to make sure it's PENDING
+        // 4. If so, change state to RUNNING
+        // 5. Decrement the eventfd
+        // 6. Take ptr from slot and return
+        self.begin_wait()?;
+        let mut consecutive_spurious = 0;
+        loop {
+            if let Some(x) = self.wake_once()? {
+                return Ok(x);
+            }
+            self.after_spurious(&mut consecutive_spurious);
+            // ...and now we go back to blocking on eventfd
+        }
+    }
+
+    /// A wakeup was sent, but it was intended for someone else. First, we let the other threads
+    /// check if the wakeup was for them...
+    fn after_spurious(&self, consecutive_spurious: &mut u32) {
+        *consecutive_spurious += 1;
+        match self.spurious_backoff {
+            Some((max, sleep)) if *consecutive_spurious >= max => {
+                *consecutive_spurious = 0;
+                thread::sleep(sleep);
+            }
+            _ => match self.yield_strategy {
+                YieldStrategy::None => {}
+                YieldStrategy::Yield => thread::yield_now(),
+                YieldStrategy::Sleep(dur) => thread::sleep(dur),
+            },
+        }
+    }
+
+    /// Like `recv`, but returns after a single wakeup, whether or not it was for this receiver.
+    ///
+    /// You get `Ok(None)` if the wakeup was for someone else (or if the payload's deadline had
+    /// passed). The receiver doesn't yield, so the caller decides what to do next; calling
+    /// `recv_once` or `recv` again carries on waiting.
+    pub fn recv_once(&mut self) -> Result<Option<Box<T>>, RecvError> {
+        if let Some(x) = self.take_spilled() { return Ok(Some(x)); }
+        self.begin_wait()?;
+        match self.wake_once()? {
+            Some((ptr, deadline)) => {
+                let x = unsafe { self.inner.unpack_box(ptr) };
+                if self.expire(deadline) { return Ok(None); }
+                self.inner.processed.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(x))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Take a payload which the sender couldn't give to anyone, if there is one.
+    fn take_spilled(&mut self) -> Option<Box<T>> {
+        let x = self.spill.pop()?;
+        self.finish_payload();
+        self.inner.processed.fetch_add(1, Ordering::Relaxed);
+        Some(x)
+    }
+
+    /// Mark this receiver as waiting for work.
+    fn begin_wait(&mut self) -> Result<(), RecvError> {
+        self.finish_payload();
+        // Receiving always leaves the state as RUNNING or ORPHANED, unless `recv_once` returned
+        // without finding any work, in which case we're still WAITING (or PENDING or LOCKED, if
+        // the sender has been busy since).
+        // The sender is allowed to (A) swap the state from WAITING to PENDING, and (B) set the
+        // state to ORPHANED.
+        // Therefore, when entering this function, the state must be one of those.
+        match self.inner.cas_state(WorkerState::Running, WorkerState::Waiting) {
+            Ok(WorkerState::Running) => {
+                /* things looks good. onward! */
+                self.record(Op::Wait, WorkerState::Waiting);
+                self.parked = true;
+                // Let any producers who are waiting for a free receiver know
+                self.ready.notify(self.inner.id);
+            }
+            Ok(s @ WorkerState::Waiting) | Ok(s @ WorkerState::Pending) | Ok(s @ WorkerState::Locked) => {
+                /* carrying on from recv_once */
+                self.record(Op::Wait, s);
+            }
+            Ok(WorkerState::Orphaned) => {
+                // If we were left WAITING (eg. by `recv_once`), the sender wrote a wakeup for us
+                // when it orphaned us
+                self.record(Op::Wait, WorkerState::Orphaned);
+                self.take_orphan_wakeup();
+                self.inner.orphan_seen.store(true, Ordering::SeqCst);
+                return Err(RecvError::Orphaned);
+            }
+            x => {
+                let x = raw_state(x);
+                bad_state(format_args!("recv::1: bad state ({}). Please report this error.{}", x, self.history_note()));
+                self.inner.set_state(WorkerState::Waiting);
+                self.parked = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Spin until we're given some work, according to `adaptive_spin`. Returns whether we were.
+    fn spin_for_work(&mut self) -> bool {
+        let spin = match self.spin.as_mut() {
+            Some(spin) => spin,
+            None => return false,
+        };
+        let mut hit = false;
+        for _ in 0..=spin.spin {
+            if self.inner.state() == Ok(WorkerState::Pending) {
+                hit = true;
+                break;
+            }
+            hint::spin_loop();
+        }
+        spin.record(hit);
+        hit
+    }
+
+    /// Block until the eventfd is signalled, and take the payload if there is one for us.
+    fn wake_once(&mut self) -> Result<Option<(*mut T, Option<Instant>)>, RecvError> {
+        if let Some(waiter) = self.inner.waiter.clone() { return self.wait_for_pending(&*waiter); }
+        // If we were given work while spinning, we can skip the poll: the read below blocks
+        // until the sender wakes us.
+        if !self.spin_for_work() {
+            self.wait(None);
+        }
+        self.take_wakeup()
+    }
+
+    /// The eventfd has been signalled: take the payload if there is one for us.
+    fn take_wakeup(&mut self) -> Result<Option<(*mut T, Option<Instant>)>, RecvError> {
+        #[cfg(feature = "metrics")]
+        let woken = Instant::now();
+        match self.inner.cas_state(WorkerState::Pending, WorkerState::Running) {
+            Ok(WorkerState::Pending) => {
+                /* this was a genuine wakeup. let's do some work! */
+                self.record(Op::Wake, WorkerState::Running);
+            }
+            Ok(s @ WorkerState::Waiting) | Ok(s @ WorkerState::Locked) => {
+                // Intended for someone else. (If we're LOCKED, our payload hasn't been woken yet,
+                // so the same goes.)
+                self.record(Op::Wake, s);
+                self.inner.spurious.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+            Ok(WorkerState::Orphaned) => {
+                // The sender left a wakeup for each receiver stuck in this loop. Take ours,
+                // so that the counter goes back to zero.
+                self.record(Op::Wake, WorkerState::Orphaned);
+                self.take_orphan_wakeup();
+                self.inner.orphan_seen.store(true, Ordering::SeqCst);
+                return Err(RecvError::Orphaned);
+            }
+            x => {
+                let x = raw_state(x);
+                bad_state(format_args!("recv::2: bad state ({}). Please report this error.{}", x, self.history_note()));
+                self.inner.set_state(WorkerState::Waiting);
+                return Ok(None);
+            }
+        }
+        // Decrement the eventfd to show that one of the inteded workers got the message.
+        // FIXME: This additional syscall is quite painful :-(
+        self.eventfd.read().unwrap();
+        self.parked = false;
+        let x = self.take_slot();
+        #[cfg(feature = "metrics")]
+        self.wakeup_latency.record(woken.elapsed());
+        Ok(Some(x))
+    }
+
+    /// We've found ourselves ORPHANED. If we were WAITING (or PENDING) when that happened, the
+    /// sender left a wakeup in the eventfd for us: take it, so that the counter goes back to
+    /// zero. This blocks until the sender has got round to writing it.
+    fn take_orphan_wakeup(&mut self) {
+        if self.parked && self.inner.waiter.is_none() { let _ = self.eventfd.read(); }
+        self.parked = false;
+    }
+
+    /// `wake_once` for receivers with their own `WaitStrategy`: the sender doesn't write to the
+    /// eventfd for us, so we watch the state instead.
+    fn wait_for_pending(&mut self, waiter: &dyn WaitStrategy) -> Result<Option<(*mut T, Option<Instant>)>, RecvError> {
+        loop {
+            match self.inner.state() {
+                Ok(s @ WorkerState::Waiting) | Ok(s @ WorkerState::Locked) => waiter.wait(&self.inner.state, s as usize),
+                Ok(WorkerState::Pending) => {
+                    // The sender may take the payload back with `drain_unwoken`
+                    if self.inner.cas_state(WorkerState::Pending, WorkerState::Running) == Ok(WorkerState::Pending) {
+                        self.record(Op::Wake, WorkerState::Running);
+                        self.parked = false;
+                        return Ok(Some(self.take_slot()));
+                    }
+                }
+                Ok(WorkerState::Orphaned) => {
+                    self.record(Op::Wake, WorkerState::Orphaned);
+                    self.take_orphan_wakeup();
+                    self.inner.orphan_seen.store(true, Ordering::SeqCst);
+                    return Err(RecvError::Orphaned);
+                }
+                x => {
+                    let x = raw_state(x);
+                    bad_state(format_args!("recv::2: bad state ({}). Please report this error.{}", x, self.history_note()));
+                    self.inner.set_state(WorkerState::Waiting);
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Take the payload, now that we're RUNNING.
+    fn take_slot(&mut self) -> (*mut T, Option<Instant>) {
+        // The sender only publishes PENDING once the slot is full (see `fill`)
+        let ptr = self.inner.slot_take().expect("recv: slot contains null ptr. Please report this error.");
+        // The slot is ours now, and so is the deadline.
+        let deadline = unsafe { (*self.inner.deadline.get()).take() };
+        self.generation = self.inner.generation.load(Ordering::Relaxed);
+        self.ttl = unsafe { *self.inner.ttl.get() };
+        self.current_burst = unsafe { (*self.inner.burst.get()).take() };
+        self.stamp_received(unsafe { (*self.inner.enqueued_at.get()).take() });
+        self.start_seq(self.inner.seq.swap(NO_SEQ, Ordering::Relaxed));
+        (ptr, deadline)
+    }
+
+    /// In ordered mode, wait for our turn to work on the payload numbered `seq`.
+    fn start_seq(&mut self, seq: u64) {
+        if seq != NO_SEQ {
+            self.sequencer.wait_turn(seq);
+            self.current_seq = seq;
+        }
+    }
+
+    /// We're done with the payload we were working on: let the one after it go ahead, and count
+    /// it towards its burst.
+    fn finish_payload(&mut self) {
+        if self.current_seq != NO_SEQ {
+            self.sequencer.finish(self.current_seq);
+            self.current_seq = NO_SEQ;
+        }
+        if let Some(burst) = self.current_burst.take() { burst.done(); }
+        if let Some((enqueued, received)) = self.current_times.take() {
+            let latency = LatencyBreakdown { queue: received - enqueued, process: received.elapsed() };
+            #[cfg(feature = "hdr")]
+            {
+                if let Some(ref hist) = self.latencies { record_latency(&mut hist.lock().unwrap(), latency); }
+            }
+            self.latency = Some(latency);
+        }
+    }
+
+    /// In timestamp mode, note when we got hold of the payload.
+    fn stamp_received(&mut self, enqueued_at: Option<Instant>) {
+        self.current_times = enqueued_at.map(|t| (t, Instant::now()));
+    }
+}
+
+/// A `Receiver` which pauses after each payload it receives, to simulate a slow worker.
+///
+/// The receiver counts as busy while it's paused, so the sender won't give it more work. This is
+/// useful for testing how work is distributed between receivers of different speeds.
+#[cfg(feature = "testing")]
+pub struct ThrottledReceiver<T, F> {
+    inner: Receiver<T>,
+    delay_fn: F,
+}
+
+#[cfg(feature = "testing")]
+impl<T, F: FnMut() -> Duration> ThrottledReceiver<T, F> {
+    /// Wrap `r`, pausing for `delay_fn()` before returning each payload.
+    pub fn new(r: Receiver<T>, delay_fn: F) -> ThrottledReceiver<T, F> {
+        ThrottledReceiver { inner: r, delay_fn }
+    }
+
+    /// Like `Receiver::recv`, but sleeps before returning the payload.
+    pub fn recv(&mut self) -> Result<Box<T>, RecvError> {
+        let x = self.inner.recv()?;
+        thread::sleep((self.delay_fn)());
+        Ok(x)
+    }
+
+    /// Stop throttling and get the underlying receiver back.
+    pub fn into_inner(self) -> Receiver<T> {
+        self.inner
+    }
+}
+
+#[cfg(feature = "testing")]
+impl<T, F> Deref for ThrottledReceiver<T, F> {
+    type Target = Receiver<T>;
+    fn deref(&self) -> &Receiver<T> { &self.inner }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct(&format!("Sender<{}>", type_name::<T>()))
+            .field("receivers", &self.workers.len())
+            .field("next_worker", &self.next_worker)
+            .field("workers_to_unblock", &self.workers_to_unblock)
+            .finish()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.finish_payload();
+        // Stop the sender giving us any more work. If it already has, `Sender::compact` will
+        // take it back.
+        if self.inner.swap_state(WorkerState::Dead) == Ok(WorkerState::Orphaned) {
+            self.take_orphan_wakeup();
+            self.inner.orphan_seen.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct(&format!("Receiver<{}>", type_name::<T>()))
+            .field("id", &self.inner.id)
+            .field("state", &self.inner.state().map_or("invalid", WorkerState::name))
+            .field("expired", &self.expired)
+            .finish()
+    }
+}
+
+impl<T> Default for Sender<T> {
+    fn default() -> Sender<T> {
+        Sender::new()
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    /// All receivers will unblock with `RecvError::Orphaned`.
+    fn drop(&mut self) {
+        if self.registry.senders.fetch_sub(1, Ordering::SeqCst) > 1 {
+            // Another handle is carrying on. Make sure the receivers we've enqueued to don't
+            // get stuck.
+            let _ = self.try_wake_all();
+            return;
+        }
+        self.refresh();
+
+        // Inform the receivers that the sender is going away.
+        //
+        // Every receiver which is blocking in recv (ie. WAITING or PENDING) will wake up, see
+        // that it's orphaned, and take one from the eventfd. Those which are PENDING and have
+        // been woken already have theirs; the others need one each. This keeps the counter
+        // balanced.
+        let mut to_unblock = self.workers_to_unblock;
+        let mut blocked = vec![];
+        for w in self.workers.iter() {
+            match w.swap_state(WorkerState::Orphaned) {
+                // A LOCKED receiver is being stolen from; as in `remove_worker`, the thief takes
+                // the payload's wakeup and the receiver needs another
+                Ok(WorkerState::Waiting) | Ok(WorkerState::Locked) => {
+                    if w.waiter.is_none() { to_unblock += 1; }
+                    blocked.push(w);
+                }
+                Ok(WorkerState::Pending) => {
+                    // It'll never take its payload, so nobody should wait for it
+                    w.abandon(&self.registry.sequencer);
+                    blocked.push(w);
+                }
+                _ => {}
+            }
+        }
+        // This only fails if the counter is already full (in nonblocking mode), in which case the
+        // receivers are all awake anyway.
+        let _ = self.eventfd.write(to_unblock);
+        if let Some(timeout) = self.drop_timeout {
+            let deadline = Instant::now() + timeout;
+            while blocked.iter().any(|w| !w.orphan_seen.load(Ordering::SeqCst)) {
+                if Instant::now() > deadline { break; }
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+/// A `Sender` which can be shared between several producer threads.
+///
+/// Each call locks the underlying sender for as long as it takes to enqueue and wake, so
+/// producers never see each other's half-finished bursts. Cloning gives another handle to the
+/// same sender.
+pub struct SharedSender<T> {
+    sender: Arc<Mutex<Sender<T>>>,
+    ready: Arc<Readiness>,
+}
+
+impl<T> Clone for SharedSender<T> {
+    fn clone(&self) -> SharedSender<T> {
+        SharedSender { sender: self.sender.clone(), ready: self.ready.clone() }
+    }
+}
+
+impl<T> SharedSender<T> {
+    /// Wrap a sender so that it can be shared.
+    pub fn new(sender: Sender<T>) -> SharedSender<T> {
+        let ready = sender.registry.ready.clone();
+        SharedSender { sender: Arc::new(Mutex::new(sender)), ready }
+    }
+
+    /// Create a new receiver handle.
+    pub fn mk_receiver(&self) -> Receiver<T> {
+        self.sender.lock().unwrap().mk_receiver()
+    }
+
+    /// Lock the underlying sender, for anything not covered here.
+    pub fn lock(&self) -> ::std::sync::MutexGuard<'_, Sender<T>> {
+        self.sender.lock().unwrap()
+    }
+}
+
+impl<T: Send> SharedSender<T> {
+    /// Enqueue a payload and wake its receiver. If no receiver is ready, you get it back.
+    pub fn send(&self, x: Box<T>) -> Option<Box<T>> {
+        let mut sender = self.sender.lock().unwrap();
+        let ret = sender.enqueue(x);
+        if ret.is_none() { sender.wake_all(); }
+        ret
+    }
+
+    /// Like `send`, but if no receiver is ready, wait until one is.
+    ///
+    /// Only the calling thread waits: the sender isn't locked in the meantime, so other
+    /// producers carry on as normal (and may take the freed receiver first, in which case this
+    /// goes back to waiting).
+    pub fn send_blocking(&self, x: Box<T>) {
+        let mut x = x;
+        // We're registered as a waiter before we look at the receivers' states, and receivers
+        // change their state before checking for waiters, so a receiver which becomes free
+        // after we've looked is guaranteed to notify us.
+        self.ready.waiters.fetch_add(1, Ordering::SeqCst);
+        loop {
+            // `send` locks the sender, so we mustn't be holding the readiness lock: someone in
+            // `wait_for_ready` takes them the other way round. A notification which comes
+            // before we've locked it again shows up as a change in `notified`.
+            let notified = self.ready.notified.load(Ordering::SeqCst);
+            match self.send(x) {
+                None => break,
+                Some(y) => x = y,
+            }
+            let guard = self.ready.lock.lock().unwrap();
+            if self.ready.notified.load(Ordering::SeqCst) == notified {
+                let _guard = self.ready.cond.wait(guard).unwrap();
+            }
+        }
+        self.ready.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Sends the bytes written to it as chunks of up to `chunk_size` bytes, one chunk per payload.
+///
+/// A chunk is enqueued as soon as it's full, and `flush` sends any partial chunk and then wakes
+/// the receivers. If a full chunk can't be enqueued because no receiver is ready, `write` wakes
+/// the receivers (so that they can get through the chunks they already have) and fails with
+/// `ErrorKind::WouldBlock`; nothing is lost, so it's safe to try again later. `flush` fails in
+/// the same way.
+pub struct SenderWriter {
+    sender: Sender<Vec<u8>>,
+    buf: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl SenderWriter {
+    pub fn new(sender: Sender<Vec<u8>>, chunk_size: usize) -> SenderWriter {
+        assert!(chunk_size > 0, "SenderWriter: chunk_size must be non-zero");
+        SenderWriter { sender, buf: Vec::with_capacity(chunk_size), chunk_size }
+    }
+
+    /// The underlying sender.
+    pub fn get_mut(&mut self) -> &mut Sender<Vec<u8>> {
+        &mut self.sender
+    }
+
+    /// Unwrap the sender. Anything which hasn't been flushed is lost.
+    pub fn into_inner(self) -> Sender<Vec<u8>> {
+        self.sender
+    }
+
+    /// Try to enqueue the buffered bytes as a chunk. Returns whether the buffer is now empty.
+    fn send_chunk(&mut self) -> bool {
+        if self.buf.is_empty() { return true; }
+        let chunk = mem::replace(&mut self.buf, Vec::with_capacity(self.chunk_size));
+        match self.sender.enqueue(Box::new(chunk)) {
+            None => true,
+            Some(chunk) => {
+                self.buf = *chunk;
+                false
+            }
+        }
+    }
+
+    fn would_block(&mut self) -> io::Error {
+        // The receivers may be sitting on chunks which haven't been woken yet
+        match self.sender.try_wake_all() {
+            Ok(()) => io::Error::new(io::ErrorKind::WouldBlock, "no receivers are ready"),
+            Err(e) => e,
+        }
+    }
+}
+
+impl io::Write for SenderWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == self.chunk_size && !self.send_chunk() {
+            return Err(self.would_block());
+        }
+        let n = cmp::min(data.len(), self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == self.chunk_size { self.send_chunk(); }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.send_chunk() { return Err(self.would_block()); }
+        self.sender.try_wake_all()
+    }
+}
+
+/// A group of senders which are sent to together.
+///
+/// `dispatch` does all the enqueueing before any of the waking, so the receivers of the first
+/// sender don't start competing for CPU with the producer while it's still enqueueing for the
+/// rest. Senders which share an eventfd (see `Sender::reuse_eventfd`) are woken with a single
+/// syscall between them.
+pub struct MultiSender<T> {
+    senders: Vec<Sender<T>>,
+}
+
+impl<T> MultiSender<T> {
+    pub fn new(senders: Vec<Sender<T>>) -> MultiSender<T> {
+        MultiSender { senders }
+    }
+
+    /// The senders, in the order they were given.
+    pub fn senders(&mut self) -> &mut [Sender<T>] {
+        &mut self.senders
+    }
+
+    pub fn into_inner(self) -> Vec<Sender<T>> {
+        self.senders
+    }
+
+    /// Wake up all the senders' receivers. See `Sender::wake_all`.
+    pub fn wake_all(&mut self) {
+        self.try_wake_all().unwrap();
+    }
+
+    /// Like `wake_all`, but returns an error if a syscall fails. The senders before the one
+    /// which failed have been woken; the rest haven't.
+    pub fn try_wake_all(&mut self) -> io::Result<()> {
+        let n = self.senders.len();
+        let mut done = vec![false; n];
+        for i in 0..n {
+            if done[i] { continue; }
+            let group: Vec<usize> = (i..n)
+                .filter(|&j| !done[j] && Arc::ptr_eq(&self.senders[i].eventfd, &self.senders[j].eventfd))
+                .collect();
+            let total: i64 = group.iter().map(|&j| self.senders[j].workers_to_unblock).sum();
+            if total != 0 {
+                self.senders[i].counters.wake_syscalls += 1;
+                self.senders[i].eventfd.write(total)?;
+            }
+            for j in group {
+                self.senders[j].woken();
+                done[j] = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Send> MultiSender<T> {
+    /// Enqueue `work[i]` to the `i`th sender, and then wake all the receivers. You get back the
+    /// payloads which didn't find a receiver, in the same shape.
+    ///
+    /// # Panics
+    ///
+    /// If `work` has more entries than there are senders.
+    pub fn dispatch(&mut self, work: Vec<Vec<Box<T>>>) -> Vec<Vec<Box<T>>> {
+        assert!(work.len() <= self.senders.len(), "dispatch: more work than senders");
+        let rejected = work.into_iter().zip(self.senders.iter_mut())
+            .map(|(xs, sender)| xs.into_iter().filter_map(|x| sender.enqueue(x)).collect())
+            .collect();
+        self.wake_all();
+        rejected
+    }
+}
+
+/// Identifies one of the receivers in a `Pool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ReceiverId(u64);
+
+/// A `Sender` which keeps track of its receivers, so that they can be removed again.
+///
+/// This is the recommended way to use the channel if the set of workers changes over time. The
+/// underlying `Sender` is available via `sender` for anything not covered here.
+pub struct Pool<T> {
+    sender: Sender<T>,
+    receivers: Vec<(ReceiverId, Arc<Worker<T>>)>,
+    next_id: u64,
+}
+
+impl<T> Pool<T> {
+    /// Create a pool with no receivers.
+    pub fn new() -> Pool<T> {
+        Pool { sender: Sender::new(), receivers: vec![], next_id: 0 }
+    }
+
+    /// Create a new receiver, which will be sent work until it's removed.
+    pub fn add_receiver(&mut self) -> (ReceiverId, Receiver<T>) {
+        let receiver = self.sender.mk_receiver();
+        let id = ReceiverId(self.next_id);
+        self.next_id += 1;
+        self.receivers.push((id, receiver.inner.clone()));
+        (id, receiver)
+    }
+
+    /// Remove a receiver from the pool. Its next (or current) call to `recv` returns
+    /// `RecvError::Orphaned`.
+    ///
+    /// If the receiver had been given a payload which it hadn't started on yet, you get the
+    /// payload back. Unknown ids are ignored.
+    pub fn remove_receiver(&mut self, id: ReceiverId) -> Option<Box<T>> {
+        let i = self.receivers.iter().position(|&(x, _)| x == id)?;
+        let (_, worker) = self.receivers.remove(i);
+        self.sender.remove_worker(&worker)
+    }
+
+    /// The receivers currently in the pool, in the order they were added.
+    pub fn receiver_ids(&self) -> Vec<ReceiverId> {
+        self.receivers.iter().map(|&(id, _)| id).collect()
+    }
+
+    /// The number of receivers currently in the pool.
+    pub fn len(&self) -> usize {
+        self.receivers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receivers.is_empty()
+    }
+
+    /// The underlying sender. Receivers created with its `mk_receiver` aren't tracked by the pool,
+    /// and can't be removed.
+    pub fn sender(&mut self) -> &mut Sender<T> {
+        &mut self.sender
+    }
+
+    /// Orphan all the receivers.
+    pub fn shutdown(self) {
+        ::std::mem::drop(self);
+    }
+}
+
+impl<T: Send> Pool<T> {
+    /// Hand out as many payloads as there are waiting receivers, and wake them up. The payloads
+    /// which didn't find a receiver are returned.
+    pub fn dispatch(&mut self, xs: Vec<Box<T>>) -> Vec<Box<T>> {
+        let res = self.sender.enqueue_batch(xs);
+        self.sender.wake_all();
+        res.into_unplaced()
+    }
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Pool<T> {
+        Pool::new()
+    }
+}
+
+impl<T> fmt::Debug for Pool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct(&format!("Pool<{}>", type_name::<T>()))
+            .field("receivers", &self.receiver_ids())
+            .finish()
+    }
+}
+
+/// Sets up a sender along with all of its receivers, for when the set of receivers never changes.
+///
+/// ```
+/// # use burst_pool::*;
+/// let (mut sender, receivers) = SenderBuilder::new().add_receivers(3).build::<u32>();
+/// assert_eq!(receivers.len(), 3);
+/// ```
+#[derive(Debug, Default)]
+pub struct SenderBuilder {
+    config: SenderConfig,
+    receivers: usize,
+}
+
+impl SenderBuilder {
+    pub fn new() -> SenderBuilder {
+        SenderBuilder::default()
+    }
+
+    /// Set up the eventfd according to `config`. See `Sender::with_config`.
+    pub fn config(mut self, config: SenderConfig) -> SenderBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Add one more receiver.
+    pub fn add_receiver(self) -> SenderBuilder {
+        self.add_receivers(1)
+    }
+
+    /// Add `n` more receivers.
+    pub fn add_receivers(mut self, n: usize) -> SenderBuilder {
+        self.receivers += n;
+        self
+    }
+
+    /// Create the sender and its receivers. The receivers are in index order.
+    pub fn build<T>(self) -> (FrozenSender<T>, Vec<Receiver<T>>) {
+        let mut sender = Sender::with_config(self.config);
+        sender.reserve(self.receivers);
+        let receivers = (0..self.receivers).map(|_| sender.mk_receiver()).collect();
+        (FrozenSender(sender), receivers)
+    }
+}
+
+/// A `Sender` which can't be given any more receivers. Made with `SenderBuilder`.
+///
+/// All the `&self` methods of `Sender` are available via `Deref`, along with the methods for
+/// sending. Adding a receiver isn't possible:
+///
+/// ```compile_fail
+/// # use burst_pool::*;
+/// let (mut sender, _) = SenderBuilder::new().add_receiver().build::<u32>();
+/// sender.mk_receiver();
+/// ```
+pub struct FrozenSender<T>(Sender<T>);
+
+impl<T: Send> FrozenSender<T> {
+    /// See `Sender::enqueue`.
+    pub fn enqueue(&mut self, x: Box<T>) -> Option<Box<T>> {
+        self.0.enqueue(x)
+    }
+
+    /// See `Sender::enqueue_value`.
+    pub fn enqueue_value(&mut self, x: T) -> Option<T> {
+        self.0.enqueue_value(x)
+    }
+
+    /// See `Sender::enqueue_deadline`.
+    pub fn enqueue_deadline(&mut self, x: Box<T>, deadline: Instant) -> Option<Box<T>> {
+        self.0.enqueue_deadline(x, deadline)
+    }
+
+    /// See `Sender::enqueue_batch`.
+    pub fn enqueue_batch(&mut self, xs: Vec<Box<T>>) -> BatchResult<T> {
+        self.0.enqueue_batch(xs)
+    }
+}
+
+impl<T> FrozenSender<T> {
+    /// See `Sender::wake_all`.
+    pub fn wake_all(&mut self) {
+        self.0.wake_all()
+    }
+
+    /// See `Sender::try_wake_all`.
+    pub fn try_wake_all(&mut self) -> io::Result<()> {
+        self.0.try_wake_all()
+    }
+
+    /// See `Sender::drain_unwoken`.
+    pub fn drain_unwoken(&mut self) -> Vec<Box<T>> {
+        self.0.drain_unwoken()
+    }
+}
+
+impl<T> Deref for FrozenSender<T> {
+    type Target = Sender<T>;
+    fn deref(&self) -> &Sender<T> { &self.0 }
+}
+
+/// Some enqueues which will be woken all at once. See `Sender::batch`.
+///
+/// The batch borrows the sender, so no other enqueues can be mixed in. If nothing was placed,
+/// committing it makes no syscall.
+pub struct Batch<'a, T: 'a> {
+    sender: &'a mut Sender<T>,
+    committed: bool,
+}
+
+impl<'a, T: Send> Batch<'a, T> {
+    /// See `Sender::enqueue`.
+    pub fn enqueue(&mut self, x: Box<T>) -> Option<Box<T>> {
+        self.sender.enqueue(x)
+    }
+
+    /// See `Sender::enqueue_value`.
+    pub fn enqueue_value(&mut self, x: T) -> Option<T> {
+        self.sender.enqueue_value(x)
+    }
+}
+
+impl<'a, T> Batch<'a, T> {
+    /// Wake the receivers now, reporting any error from the syscall. Dropping the batch does the
+    /// same, but panics on error (see `Sender::wake_all`).
+    pub fn commit(mut self) -> io::Result<()> {
+        self.committed = true;
+        self.sender.try_wake_all()
+    }
+}
+
+impl<'a, T> Deref for Batch<'a, T> {
+    type Target = Sender<T>;
+    fn deref(&self) -> &Sender<T> { self.sender }
+}
+
+impl<'a, T> Drop for Batch<'a, T> {
+    fn drop(&mut self) {
+        if self.committed { return; }
+        if thread::panicking() {
+            let _ = self.sender.try_wake_all();
+        } else {
+            self.sender.wake_all();
+        }
+    }
+}
+
+/// A `Sender` whose receivers run on threads which can borrow from the enclosing stack frame.
+///
+/// See `scoped_channel`.
+pub struct ChannelScope<'scope, 'env: 'scope, T: 'env> {
+    sender: Sender<T>,
+    scope: &'scope thread::Scope<'scope, 'env>,
+    cancel: CancelToken,
+}
+
+impl<'scope, 'env, T: Send + 'env> ChannelScope<'scope, 'env, T> {
+    /// Create a new receiver and pass it to `f`, running on a new scoped thread.
+    ///
+    /// The thread will be joined before `scoped_channel` returns, after the sender has been
+    /// dropped. `f` should therefore return when the receiver reports `RecvError::Orphaned`.
+    /// If `f` can spend a long time away from `recv`, `scoped_channel` has to wait for it to get
+    /// back; use `spawn_cancellable` to let it know that it should stop.
+    pub fn spawn<F>(&mut self, f: F) where F: FnOnce(Receiver<T>) + Send + 'scope {
+        let receiver = self.sender.mk_receiver();
+        self.scope.spawn(move || f(receiver));
+    }
+
+    /// Like `spawn`, but `f` is also given a token which is cancelled when the scope ends, just
+    /// before the sender is dropped.
+    pub fn spawn_cancellable<F>(&mut self, f: F) where F: FnOnce(Receiver<T>, CancelToken) + Send + 'scope {
+        let receiver = self.sender.mk_receiver();
+        let cancel = self.cancel.clone();
+        self.scope.spawn(move || f(receiver, cancel));
+    }
+}
+
+/// Tells a receiver thread started by `ChannelScope::spawn_cancellable` that it should finish
+/// up.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<'scope, 'env, T> Deref for ChannelScope<'scope, 'env, T> {
+    type Target = Sender<T>;
+    fn deref(&self) -> &Sender<T> { &self.sender }
+}
+
+impl<'scope, 'env, T> DerefMut for ChannelScope<'scope, 'env, T> {
+    fn deref_mut(&mut self) -> &mut Sender<T> { &mut self.sender }
+}
+
+/// Create a channel whose receiver threads may borrow non-`'static` data.
+///
+/// `f` is given a `ChannelScope`, which can be used as a `Sender` and for spawning receiver
+/// threads. When `f` returns the sender is dropped, orphaning the receivers, and all the
+/// threads are joined. This means that both the payloads and the receiver closures can borrow
+/// from the caller's stack, without needing an `Arc`.
+///
+/// ```
+/// # use burst_pool::*;
+/// # use std::sync::atomic::*;
+/// let data = vec![1u8, 2, 3, 4];
+/// let total = AtomicUsize::new(0);
+/// scoped_channel(|chan| {
+///     chan.spawn(|mut receiver: Receiver<&[u8]>| {
+///         while let Ok(xs) = receiver.recv() {
+///             total.fetch_add(xs.iter().map(|&x| x as usize).sum(), Ordering::SeqCst);
+///         }
+///     });
+/// #   std::thread::sleep(std::time::Duration::from_millis(10));
+///     chan.enqueue(Box::new(&data[..]));
+///     chan.wake_all();
+/// #   while total.load(Ordering::SeqCst) == 0 { std::thread::yield_now(); }
+/// });
+/// assert_eq!(total.into_inner(), 10);
+/// ```
+pub fn scoped_channel<'env, T, F, R>(f: F) -> R
+where
+    T: Send + 'env,
+    F: for<'scope> FnOnce(&mut ChannelScope<'scope, 'env, T>) -> R,
+{
+    thread::scope(|scope| {
+        let mut chan = ChannelScope { sender: Sender::new(), scope, cancel: CancelToken::default() };
+        let ret = f(&mut chan);
+        // Orphan the receivers, so that the threads can be joined at the end of the scope
+        chan.cancel.cancel();
+        ::std::mem::drop(chan);
+        ret
+    })
+}
+
+/// A `Sender` which runs each of its receivers on a thread of its own, passing every payload to
+/// a closure.
+///
+/// Use it as a `Sender`. Dropping it orphans the receivers and joins their threads; payloads
+/// which have been enqueued but not yet taken are dropped, so use `wait_for_ready` first if you
+/// need them all handled. If a closure panicked, the panic is propagated by the drop.
+///
+/// ```
+/// # use burst_pool::*;
+/// # use std::sync::Arc;
+/// # use std::sync::atomic::*;
+/// let total = Arc::new(AtomicUsize::new(0));
+/// let mut chan = BurstChannel::new();
+/// let t = total.clone();
+/// chan.spawn(move |x: Box<usize>| { t.fetch_add(*x, Ordering::SeqCst); });
+/// chan.wait_for_ready(1, None);
+/// chan.enqueue(Box::new(5));
+/// chan.wake_all();
+/// chan.wait_for_ready(1, None);
+/// drop(chan);
+/// assert_eq!(total.load(Ordering::SeqCst), 5);
+/// ```
+pub struct BurstChannel<T> {
+    sender: ManuallyDrop<Sender<T>>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<T> BurstChannel<T> {
+    /// Create a channel with no receivers.
+    pub fn new() -> BurstChannel<T> {
+        BurstChannel { sender: ManuallyDrop::new(Sender::new()), threads: vec![] }
+    }
+
+    /// The number of receiver threads which have been spawned.
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+}
+
+impl<T: Send + 'static> BurstChannel<T> {
+    /// Create a new receiver, and spawn a thread which passes everything it receives to
+    /// `consume`, until it's orphaned.
+    pub fn spawn<F>(&mut self, mut consume: F) where F: FnMut(Box<T>) + Send + 'static {
+        let mut receiver = self.sender.mk_receiver();
+        self.threads.push(thread::spawn(move || {
+            while let Ok(x) = receiver.recv() { consume(x); }
+        }));
+    }
+}
+
+impl<T> Default for BurstChannel<T> {
+    fn default() -> BurstChannel<T> {
+        BurstChannel::new()
+    }
+}
+
+impl<T> Deref for BurstChannel<T> {
+    type Target = Sender<T>;
+    fn deref(&self) -> &Sender<T> { &self.sender }
+}
+
+impl<T> DerefMut for BurstChannel<T> {
+    fn deref_mut(&mut self) -> &mut Sender<T> { &mut self.sender }
+}
+
+impl<T> Drop for BurstChannel<T> {
+    fn drop(&mut self) {
+        // Orphan the receivers first, so that the threads finish
+        unsafe { ManuallyDrop::drop(&mut self.sender); }
+        for th in self.threads.drain(..) {
+            if let Err(e) = th.join() {
+                if !thread::panicking() { panic::resume_unwind(e); }
+            }
+        }
+    }
+}
+
+/// Apply `f` to every input on a pool of `workers` threads, returning the results in input order.
+///
+/// The threads are spawned at the start and joined at the end, so this is only worthwhile for
+/// one-off bursts of work. Inputs which find every worker busy are retried until one frees up,
+/// so nothing is dropped. `f` may borrow from the caller's stack.
+///
+/// ```
+/// # use burst_pool::*;
+/// let squares = burst_map((0..10).collect(), 3, |x: u32| x * x);
+/// assert_eq!(squares, vec![0, 1, 4, 9, 16, 25, 36, 49, 64, 81]);
+/// ```
+pub fn burst_map<T, R, F>(inputs: Vec<T>, workers: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Send + Clone,
+{
+    assert!(workers > 0, "burst_map: need at least one worker");
+    let n = inputs.len();
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    scoped_channel(|chan| {
+        for _ in 0..workers {
+            let (f, tx) = (f.clone(), tx.clone());
+            chan.spawn(move |mut receiver| {
+                while let Ok(x) = receiver.recv() {
+                    let (i, x) = *x;
+                    if tx.send((i, f(x))).is_err() { break; }
+                }
+            });
+        }
+        ::std::mem::drop(tx);
+        let mut inputs = inputs.into_iter().enumerate();
+        let mut next = inputs.next();
+        while let Some(x) = next.take() {
+            match chan.enqueue(Box::new(x)) {
+                None => next = inputs.next(),
+                Some(x) => {
+                    // Everyone's busy. Get them started on what they've got, and try again.
+                    chan.wake_all();
+                    thread::yield_now();
+                    next = Some(*x);
+                }
+            }
+        }
+        chan.wake_all();
+        let mut results: Vec<Option<R>> = (0..n).map(|_| None).collect();
+        for _ in 0..n {
+            let (i, r) = rx.recv().expect("burst_map: a worker panicked");
+            results[i] = Some(r);
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    })
+}
+
+/// Call `f` for every index in `range`, on a pool of `workers` threads, returning when they've
+/// all been done.
+///
+/// The range is split into one contiguous chunk per worker. As with `burst_map`, the threads are
+/// spawned at the start and joined at the end, and `f` may borrow from the caller's stack.
+///
+/// ```
+/// # use burst_pool::*;
+/// # use std::sync::atomic::*;
+/// let total = AtomicUsize::new(0);
+/// burst_for(0..10, 3, |i| { total.fetch_add(i, Ordering::SeqCst); });
+/// assert_eq!(total.into_inner(), 45);
+/// ```
+pub fn burst_for<F>(range: Range<usize>, workers: usize, f: F)
+where
+    F: Fn(usize) + Send + Sync,
+{
+    assert!(workers > 0, "burst_for: need at least one worker");
+    if range.start >= range.end { return; }
+    let chunk = (range.end - range.start).div_ceil(workers);
+    let chunks: Vec<Range<usize>> = (range.start..range.end).step_by(chunk)
+        .map(|start| start..cmp::min(start + chunk, range.end))
+        .collect();
+    let n = chunks.len();
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    let f = &f;
+    scoped_channel(|chan| {
+        for _ in 0..n {
+            let tx = tx.clone();
+            chan.spawn(move |mut receiver: Receiver<Range<usize>>| {
+                while let Ok(chunk) = receiver.recv() {
+                    for i in *chunk { f(i); }
+                    if tx.send(()).is_err() { break; }
+                }
+            });
+        }
+        ::std::mem::drop(tx);
+        for chunk in chunks {
+            let mut x = Box::new(chunk);
+            while let Some(y) = chan.enqueue(x) {
+                // A worker hasn't started waiting yet
+                chan.wake_all();
+                thread::yield_now();
+                x = y;
+            }
+        }
+        chan.wake_all();
+        // Wait for the chunks to be done before the receivers are orphaned
+        for _ in 0..n {
+            rx.recv().expect("burst_for: a worker panicked");
+        }
+    })
+}
+
+static BAD_STATE_HOOK: Mutex<Option<fn(&str)>> = Mutex::new(None);
+
+/// Set the function which is called when a receiver is found in a state it should never be in.
+///
+/// Reaching such a state indicates a bug in this library. In debug builds it causes a panic and
+/// the hook is never called. In release builds the hook is called with a description of the
+/// problem (the default is to print it to stderr), and then the library attempts to carry on:
+/// `enqueue` treats the receiver as busy and skips it, and `recv` puts the receiver back into
+/// the waiting state.
+pub fn set_bad_state_hook(hook: fn(&str)) {
+    *BAD_STATE_HOOK.lock().unwrap() = Some(hook);
+}
+
+/// Panics in debug builds; reports via the bad-state hook in release builds.
+#[cfg(debug_assertions)]
+fn bad_state(msg: fmt::Arguments) {
+    panic!("{}", msg);
+}
+
+/// Panics in debug builds; reports via the bad-state hook in release builds.
+#[cfg(not(debug_assertions))]
+fn bad_state(msg: fmt::Arguments) {
+    let msg = msg.to_string();
+    match *BAD_STATE_HOOK.lock().unwrap() {
+        Some(hook) => hook(&msg),
+        None => eprintln!("burst_pool: {}", msg),
+    }
+}
+
+/// Remove the entries of a per-receiver list which correspond to dead receivers.
+fn remove_dead<X>(xs: &mut Vec<X>, dead: &[bool]) {
+    let mut i = 0;
+    xs.retain(|_| { i += 1; !dead.get(i - 1).cloned().unwrap_or(false) });
+}
+
+/// A timeout for `poll`, rounded up to the next millisecond. `None` means forever.
+fn timeout_ms(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        Some(t) => cmp::min(t.as_nanos().div_ceil(1_000_000), i32::MAX as u128) as i32,
+        None => -1,
+    }
+}
+
+fn num_cores() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RecvError {
+    Orphaned,
+}
+
+/// How long a payload spent waiting for a receiver, and how long the receiver spent on it. See
+/// `Receiver::latency`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyBreakdown {
+    /// From being enqueued to being taken out of the slot by the receiver.
+    pub queue: Duration,
+    /// From being taken out of the slot to being finished with.
+    pub process: Duration,
+}
+
+/// What woke up `Receiver::recv_interruptible`.
+#[derive(Debug, PartialEq)]
+pub enum Interrupted<T> {
+    /// A payload for this receiver
+    Work(T),
+    /// The extra fd became readable
+    External,
+}
+
+/// Something `Sender::check_invariants` found wrong. Receivers are identified by their index.
+#[cfg(debug_assertions)]
+#[derive(Debug, PartialEq)]
+pub enum InvariantViolation {
+    /// The receiver's state isn't one of the valid ones
+    BadState { receiver: usize, state: usize },
+    /// The receiver is pending, but there's nothing in its slot
+    PendingWithoutPayload { receiver: usize },
+    /// The receiver is waiting, but there's something in its slot
+    WaitingWithPayload { receiver: usize },
+    /// The eventfd's counter is negative, or more than the number of receivers which could take
+    /// from it
+    EventFdBalance { balance: i64, busy: usize },
+}
+
+#[cfg(feature = "hdr")]
+fn record_latency(hist: &mut hdrhistogram::Histogram<u64>, latency: LatencyBreakdown) {
+    let nanos = (latency.queue + latency.process).as_nanos().min(u128::from(u64::MAX)) as u64;
+    // The histogram resizes itself to fit, so this only fails if it can't get that big
+    let _ = hist.record(nanos);
+}
+
+/// A histogram of durations, in power-of-two buckets of nanoseconds.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyHistogram {
+    buckets: [u64; 32],
+    total_nanos: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl LatencyHistogram {
+    fn record(&mut self, dur: Duration) {
+        let nanos = dur.as_nanos().min(u128::from(u64::MAX)) as u64;
+        let bucket = (64 - nanos.leading_zeros() as usize).min(self.buckets.len() - 1);
+        self.buckets[bucket] += 1;
+        self.total_nanos = self.total_nanos.saturating_add(nanos);
+    }
+
+    /// The number of durations in each bucket. Bucket `i` covers `[2^(i-1), 2^i)` ns, except
+    /// that bucket 0 is exactly zero and the last bucket includes everything bigger.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// The number of durations recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The average of the durations recorded, if there are any.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 { return None; }
+        Some(Duration::from_nanos(self.total_nanos / count))
+    }
+}
+
+/// Identifies a payload sent with `Sender::enqueue_with_token`, so that it can be cancelled.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EnqueueToken {
+    worker: usize,
+    // The receiver's `id`. Its index can change (eg. in `compact`), and tickets are per-receiver.
+    id: usize,
+    ticket: u64,
+}
+
+impl EnqueueToken {
+    /// The index of the receiver which the payload was given to, at the time it was given.
+    pub fn worker(&self) -> usize {
+        self.worker
+    }
+}
+
+/// The future returned by `Sender::enqueue_async`.
+pub struct EnqueueAsync<'a, T: 'a> {
+    sender: &'a mut Sender<T>,
+    item: Option<Box<T>>,
+    registered: bool,
+}
+
+impl<'a, T: Send> Future for EnqueueAsync<'a, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        let x = this.item.take().expect("EnqueueAsync polled after completion");
+        let ready = this.sender.registry.ready.clone();
+        // As in `SharedSender::send_blocking`, register before looking at the states
+        if !this.registered {
+            ready.waiters.fetch_add(1, Ordering::SeqCst);
+            this.registered = true;
+        }
+        {
+            let mut wakers = ready.lock.lock().unwrap();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        match this.sender.enqueue(x) {
+            None => {
+                this.sender.wake_all();
+                ready.waiters.fetch_sub(1, Ordering::SeqCst);
+                this.registered = false;
+                Poll::Ready(())
+            }
+            Some(x) => {
+                this.item = Some(x);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for EnqueueAsync<'a, T> {
+    fn drop(&mut self) {
+        if self.registered {
+            self.sender.registry.ready.waiters.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A bounded queue in front of a `Sender`, for async producers.
+///
+/// `send` adds payloads to the queue, and waits while it's full. The `AsyncDrain` returned
+/// alongside moves them from the queue to receivers as they become ready, and should be spawned
+/// as a task on your executor. Cloning gives another handle to the same queue.
+pub struct AsyncSender<T> {
+    queue: Arc<AsyncQueue<T>>,
+}
+
+struct AsyncQueue<T> {
+    items: Mutex<AsyncQueueState<T>>,
+    bound: usize,
+}
+
+struct AsyncQueueState<T> {
+    items: VecDeque<Box<T>>,
+    senders: usize,
+    drain: Option<Waker>,   // the `AsyncDrain`, waiting for items
+    space: Vec<Waker>,      // `send`s waiting for the queue to have room
+}
+
+impl<T> AsyncSender<T> {
+    /// Queue up to `bound` payloads for `sender`'s receivers.
+    ///
+    /// # Panics
+    ///
+    /// If `bound` is zero.
+    pub fn new(sender: Sender<T>, bound: usize) -> (AsyncSender<T>, AsyncDrain<T>) {
+        assert!(bound > 0, "AsyncSender: bound must be non-zero");
+        let queue = Arc::new(AsyncQueue {
+            items: Mutex::new(AsyncQueueState {
+                items: VecDeque::with_capacity(bound),
+                senders: 1,
+                drain: None,
+                space: Vec::new(),
+            }),
+            bound,
+        });
+        let drain = AsyncDrain { sender: Some(sender), queue: queue.clone(), registered: false };
+        (AsyncSender { queue }, drain)
+    }
+
+    /// Add `x` to the queue, waiting until there's room.
+    pub fn send(&self, x: Box<T>) -> AsyncSend<'_, T> {
+        AsyncSend { queue: &self.queue, item: Some(x) }
+    }
+
+    /// The number of payloads in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.items.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Clone for AsyncSender<T> {
+    fn clone(&self) -> AsyncSender<T> {
+        self.queue.items.lock().unwrap().senders += 1;
+        AsyncSender { queue: self.queue.clone() }
+    }
+}
+
+impl<T> Drop for AsyncSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.queue.items.lock().unwrap();
+        state.senders -= 1;
+        // The drain finishes once the last sender has gone
+        if state.senders == 0 {
+            if let Some(waker) = state.drain.take() { waker.wake(); }
+        }
+    }
+}
+
+/// The future returned by `AsyncSender::send`.
+pub struct AsyncSend<'a, T: 'a> {
+    queue: &'a AsyncQueue<T>,
+    item: Option<Box<T>>,
+}
+
+impl<'a, T> Future for AsyncSend<'a, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        let mut state = this.queue.items.lock().unwrap();
+        if state.items.len() >= this.queue.bound {
+            if !state.space.iter().any(|w| w.will_wake(cx.waker())) {
+                state.space.push(cx.waker().clone());
+            }
+            return Poll::Pending;
+        }
+        let x = this.item.take().expect("AsyncSend polled after completion");
+        state.items.push_back(x);
+        if let Some(waker) = state.drain.take() { waker.wake(); }
+        Poll::Ready(())
+    }
+}
+
+/// Moves payloads from an `AsyncSender`'s queue to the receivers. Finishes, giving the `Sender`
+/// back, once all the `AsyncSender`s have been dropped and the queue is empty.
+///
+/// The last payloads may not have been taken by their receivers yet at that point, and dropping
+/// the `Sender` would discard them. Use `Sender::wait_for_ready` to wait for everyone to finish.
+pub struct AsyncDrain<T> {
+    sender: Option<Sender<T>>,
+    queue: Arc<AsyncQueue<T>>,
+    registered: bool,
+}
+
+impl<T: Send> Future for AsyncDrain<T> {
+    type Output = Sender<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Sender<T>> {
+        let this = &mut *self;
+        let sender = this.sender.as_mut().expect("AsyncDrain polled after completion");
+        let ready = sender.registry.ready.clone();
+        // As in `EnqueueAsync`, register before looking at the states
+        if !this.registered {
+            ready.waiters.fetch_add(1, Ordering::SeqCst);
+            this.registered = true;
+        }
+        {
+            let mut wakers = ready.lock.lock().unwrap();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        let mut state = this.queue.items.lock().unwrap();
+        state.drain = Some(cx.waker().clone());
+        let mut moved = false;
+        while let Some(x) = state.items.pop_front() {
+            match sender.enqueue(x) {
+                None => moved = true,
+                Some(x) => {
+                    state.items.push_front(x);
+                    break;
+                }
+            }
+        }
+        if moved {
+            sender.wake_all();
+            for waker in state.space.drain(..) { waker.wake(); }
+        }
+        if state.items.is_empty() && state.senders == 0 {
+            state.drain = None;
+            drop(state);
+            ready.waiters.fetch_sub(1, Ordering::SeqCst);
+            this.registered = false;
+            return Poll::Ready(this.sender.take().unwrap());
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for AsyncDrain<T> {
+    fn drop(&mut self) {
+        if self.registered {
+            if let Some(ref sender) = self.sender {
+                sender.registry.ready.waiters.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// Something a `Sender` did, as recorded when tracing is on. See `Sender::take_trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A payload was given to the receiver with this index.
+    Enqueue { at: Instant, receiver: usize },
+    /// The eventfd was written to, waking this many receivers.
+    Wake { at: Instant, receivers: u64 },
+}
+
+/// What happened to one of the payloads passed to `Sender::enqueue_batch`.
+#[derive(Debug)]
+pub enum Placement<T> {
+    /// The payload was given to the receiver with this index.
+    Placed(usize),
+    /// No receiver was ready, so you get the payload back.
+    Unplaced(Box<T>),
+}
+
+/// The outcome of `Sender::enqueue_batch`: one `Placement` per payload, in the order they were
+/// passed in.
+#[derive(Debug)]
+pub struct BatchResult<T> {
+    pub placements: Vec<Placement<T>>,
+}
+
+impl<T> BatchResult<T> {
+    /// The indices of the receivers which were given payloads.
+    pub fn placed(&self) -> Vec<usize> {
+        self.placements.iter().filter_map(|p| match *p {
+            Placement::Placed(i) => Some(i),
+            Placement::Unplaced(_) => None,
+        }).collect()
+    }
+
+    /// The payloads which couldn't be placed.
+    pub fn into_unplaced(self) -> Vec<Box<T>> {
+        self.placements.into_iter().filter_map(|p| match p {
+            Placement::Placed(_) => None,
+            Placement::Unplaced(x) => Some(x),
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sleep_ms(x: u64) {
+        thread::sleep(Duration::from_millis(x));
+    }
+
+    /// Make every receiver look like it's blocking in `recv`, without needing any threads.
+    fn park_all<T>(sender: &Sender<T>) {
+        for w in sender.workers.iter() {
+            w.set_state(WorkerState::Waiting);
+        }
+    }
+
+    fn has_work<T>(r: &Receiver<T>) -> bool {
+        !r.inner.slot.load(Ordering::SeqCst).is_null()
+    }
+
+    /// Take the payload out of a receiver's slot and put it back to waiting, as if it had been
+    /// woken up, done the work, and called `recv` again.
+    fn consume<T>(r: &Receiver<T>) -> Box<T> {
+        let ptr = r.inner.slot.swap(ptr::null_mut(), Ordering::SeqCst);
+        assert!(!ptr.is_null());
+        r.inner.set_state(WorkerState::Waiting);
+        unsafe { Box::from_raw(ptr) }
+    }
+
+    #[test]
+    fn expired_payloads_are_skipped() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut receiver = sender.mk_receiver();
+        let th = thread::spawn(move || {
+            let x = receiver.recv().unwrap();
+            (*x, receiver.expired())
+        });
+        sleep_ms(10);
+
+        let deadline = Instant::now() + Duration::from_millis(1);
+        assert!(sender.enqueue_deadline(Box::new(1), deadline).is_none());
+        sleep_ms(5);
+        sender.wake_all();
+        sleep_ms(10);
+
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        sender.wake_all();
+        assert_eq!(th.join().unwrap(), (2, 1));
+    }
+
+    #[test]
+    fn set_next_worker() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+
+        sender.set_next_worker(2);
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        assert!(has_work(&rs[2]));
+        assert!(has_work(&rs[0]));
+        assert!(!has_work(&rs[1]));
+    }
+
+    #[test]
+    fn enqueue_fast_path() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        // Only the first receiver is ever ready
+        rs[0].inner.set_state(WorkerState::Waiting);
+
+        for i in 0..10 {
+            assert!(sender.enqueue(Box::new(i)).is_none());
+            assert_eq!(*consume(&rs[0]), i);
+        }
+        // Without the fast path, each enqueue after the first would try all three receivers
+        assert_eq!(sender.counters().cas_attempts, 10);
+
+        // When the cached receiver is busy we fall back to the full scan
+        park_all(&sender);
+        rs[0].inner.set_state(WorkerState::Running);
+        assert!(sender.enqueue(Box::new(10)).is_none());
+        assert!(has_work(&rs[1]));
+    }
+
+    #[test]
+    fn reset_counters() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        for i in 0..3 {
+            sender.enqueue(Box::new(i));
+        }
+        sender.wake_all();
+
+        let before = sender.reset_counters();
+        assert_eq!(before, Counters { cas_attempts: 5, sent: 2, dropped: 1, wake_syscalls: 1 });
+        assert_eq!(sender.counters(), Counters::default());
+
+        consume(&rs[0]);
+        sender.enqueue(Box::new(3));
+        assert_eq!(sender.counters(), Counters { cas_attempts: 2, sent: 1, dropped: 0, wake_syscalls: 0 });
+    }
+
+    #[test]
+    fn recv_while() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || {
+            let n = std::cell::Cell::new(0);
+            r.recv_while(|| n.get() < 3, |_| n.set(n.get() + 1)).unwrap();
+            (n.get(), r.processed())
+        });
+        for i in 0..3 {
+            while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+            assert!(sender.enqueue(Box::new(i)).is_none());
+            sender.wake_all();
+        }
+        assert_eq!(th.join().unwrap(), (3, 3));
+        assert!(sender.enqueue(Box::new(3)).is_some());
+    }
+
+    #[test]
+    fn enqueue_coalesced() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+
+        assert!(sender.enqueue_coalesced(Box::new(5)).is_none());
+        assert_eq!(sender.enqueue_coalesced(Box::new(5)).map(|x| *x), Some(5));
+        assert!(sender.enqueue_coalesced(Box::new(6)).is_none());
+        assert_eq!(sender.workers_to_unblock, 2);
+        assert!(!has_work(&rs[2]));
+        for r in &rs[..2] {
+            assert_eq!(r.inner.state(), Ok(WorkerState::Pending));
+        }
+
+        // Once woken, the payloads are no longer ours to compare against
+        sender.wake_all();
+        assert!(sender.enqueue_coalesced(Box::new(5)).is_none());
+        assert!(has_work(&rs[2]));
+    }
+
+    #[test]
+    fn can_enqueue() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        assert_eq!(sender.can_enqueue(), None);
+        park_all(&sender);
+        sender.set_next_worker(1);
+        assert_eq!(sender.can_enqueue(), Some(1));
+        assert_eq!(sender.can_enqueue(), Some(1));
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        assert!(has_work(&rs[1]));
+        assert_eq!(sender.can_enqueue(), Some(0));
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        assert_eq!(sender.can_enqueue(), None);
+        assert_eq!(sender.counters().cas_attempts, 3);
+    }
+
+    #[test]
+    fn outstanding() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        assert_eq!(sender.outstanding(), 0);
+        for i in 0..3 {
+            assert!(sender.enqueue(Box::new(i)).is_none());
+        }
+        assert_eq!(sender.outstanding(), 3);
+        sender.wake_all();
+        consume(&rs[0]);
+        assert_eq!(sender.outstanding(), 2);
+        for r in &rs[1..] { consume(r); }
+        assert_eq!(sender.outstanding(), 0);
+    }
+
+    #[test]
+    fn recv_once() {
+        let mut sender: Sender<u32> = Sender::new();
+        // The target has no thread, so the wakeup stays in the eventfd for everyone to see
+        let target = sender.mk_receiver();
+        let threads: Vec<_> = (0..2).map(|_| {
+            let mut r = sender.mk_receiver();
+            thread::spawn(move || (r.recv_once(), r))
+        }).collect();
+        target.inner.set_state(WorkerState::Waiting);
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+
+        sender.set_next_worker(0);
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        sender.wake_all();
+        for th in threads {
+            let (res, r) = th.join().unwrap();
+            assert_eq!(res, Ok(None));
+            assert_eq!(r.spurious_wakeups(), 1);
+            assert_eq!(r.inner.state(), Ok(WorkerState::Waiting));
+        }
+        assert_eq!(*consume(&target), 1);
+        assert_eq!(sender.drain_eventfd().unwrap(), 1);
+    }
+
+    #[test]
+    fn weighted_enqueue() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        sender.set_weight(2, 2);
+
+        let mut counts = [0; 3];
+        for i in 0..400 {
+            assert!(sender.enqueue(Box::new(i)).is_none());
+            let idx = rs.iter().position(has_work).unwrap();
+            consume(&rs[idx]);
+            counts[idx] += 1;
+        }
+        assert_eq!(counts, [100, 100, 200]);
+
+        // Busy receivers are skipped, whatever their weight
+        assert!(sender.enqueue(Box::new(0)).is_none());
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        assert!(rs.iter().all(has_work));
+        assert!(sender.enqueue(Box::new(3)).is_some());
+    }
+
+    #[test]
+    fn enqueue_spin() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut receiver = sender.mk_receiver();
+        let th = thread::spawn(move || {
+            let x = *receiver.recv().unwrap();
+            sleep_ms(5);
+            let y = *receiver.recv().unwrap();
+            (x, y)
+        });
+        sleep_ms(10);
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        sender.wake_all();
+        sleep_ms(1);
+
+        // The receiver is busy for now...
+        let x = sender.enqueue_spin(Box::new(2), 0).unwrap();
+        // ...but will be ready soon
+        assert!(sender.enqueue_spin(x, 1 << 28).is_none());
+        sender.wake_all();
+        assert_eq!(th.join().unwrap(), (1, 2));
+    }
+
+    #[test]
+    fn enqueue_batch() {
+        let mut sender: Sender<u32> = Sender::new();
+        let _rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+
+        let res = sender.enqueue_batch((0..5).map(Box::new).collect());
+        assert_eq!(res.placed(), vec![0, 1, 2]);
+        let unplaced: Vec<u32> = res.into_unplaced().into_iter().map(|x| *x).collect();
+        assert_eq!(unplaced, vec![3, 4]);
+        assert_eq!(sender.workers_to_unblock, 3);
+    }
+
+    #[test]
+    fn drain_unwoken() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        sender.wake_all();
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        assert!(sender.enqueue(Box::new(3)).is_none());
+
+        // The first payload has been woken, so it's not ours to take back
+        let xs: Vec<u32> = sender.drain_unwoken().into_iter().map(|x| *x).collect();
+        assert_eq!(xs, vec![2, 3]);
+        assert_eq!(sender.workers_to_unblock, 0);
+        assert!(has_work(&rs[0]));
+        for r in &rs[1..] {
+            assert!(!has_work(r));
+            assert_eq!(r.inner.state(), Ok(WorkerState::Waiting));
+        }
+    }
+
+    #[test]
+    fn cancel() {
+        let mut sender: Sender<u32> = Sender::new();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut workers = vec![];
+        let ths: Vec<_> = (0..2).map(|_| {
+            let mut r = sender.mk_receiver();
+            workers.push(r.inner.clone());
+            let tx = tx.clone();
+            thread::spawn(move || while let Ok(x) = r.recv() { tx.send(*x).unwrap(); })
+        }).collect();
+        while workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+
+        let t1 = sender.enqueue_with_token(Box::new(1)).unwrap();
+        let t2 = sender.enqueue_with_token(Box::new(2)).unwrap();
+        assert_eq!((t1.worker(), t2.worker()), (0, 1));
+        assert_eq!(sender.cancel(t1).map(|x| *x), Some(1));
+        assert_eq!(sender.workers_to_unblock, 1);
+        sender.wake_all();
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(2));
+        assert!(rx.recv_timeout(Duration::from_millis(20)).is_err());
+        // Already woken, and received
+        assert!(sender.cancel(t2).is_none());
+        drop(sender);
+        for th in ths { th.join().unwrap(); }
+    }
+
+    #[test]
+    fn cancel_after_compact() {
+        let mut sender: Sender<u32> = Sender::new();
+        let r0 = sender.mk_receiver();
+        let (_r1, _r2) = (sender.mk_receiver(), sender.mk_receiver());
+        park_all(&sender);
+        r0.inner.set_state(WorkerState::Running);
+        let t1 = sender.enqueue_with_token(Box::new(1)).unwrap();
+        let t2 = sender.enqueue_with_token(Box::new(2)).unwrap();
+        assert_eq!((t1.worker(), t2.worker()), (1, 2));
+        drop(r0);
+        assert!(sender.compact().is_empty());
+        assert_eq!(sender.cancel(t1).map(|x| *x), Some(1));
+        assert_eq!(sender.cancel(t2).map(|x| *x), Some(2));
+        assert_eq!(sender.workers_to_unblock, 0);
+    }
+
+    #[test]
+    fn cancel_after_receiver_reused() {
+        let mut sender: Sender<u32> = Sender::new();
+        let r = sender.mk_receiver();
+        park_all(&sender);
+
+        let t1 = sender.enqueue_with_token(Box::new(1)).unwrap();
+        // The receiver takes the payload early and is given another, all before wake_all
+        assert_eq!(*consume(&r), 1);
+        let t2 = sender.enqueue_with_token(Box::new(2)).unwrap();
+        assert!(sender.cancel(t1).is_none());
+        assert_eq!(sender.cancel(t2).map(|x| *x), Some(2));
+    }
+
+    #[test]
+    fn worker_state_transitions() {
+        for x in 0..6 {
+            assert_eq!(WorkerState::try_from(x).map(|s| s as usize), Ok(x));
+        }
+        assert_eq!(WorkerState::try_from(6), Err(6));
+
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        assert_eq!(w.state(), Ok(WorkerState::Running));
+        // Running -> Waiting
+        let th = thread::spawn(move || { let x = *r.recv().unwrap(); (x, r.recv()) });
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        // Waiting -> Pending -> Waiting
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        assert_eq!(w.state(), Ok(WorkerState::Pending));
+        assert_eq!(sender.drain_unwoken().len(), 1);
+        assert_eq!(w.state(), Ok(WorkerState::Waiting));
+        // Waiting -> Pending -> Locked -> Pending
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        assert!(sender.enqueue_coalesced(Box::new(2)).is_some());
+        assert_eq!(w.state(), Ok(WorkerState::Pending));
+        // Pending -> Running -> Waiting
+        sender.wake_all();
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        // Waiting -> Orphaned
+        ::std::mem::drop(sender);
+        // Orphaned -> Dead, once the thread has dropped the receiver
+        assert_eq!(th.join().unwrap(), (2, Err(RecvError::Orphaned)));
+        assert_eq!(w.state(), Ok(WorkerState::Dead));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "enqueue: bad state (7)")]
+    fn bad_state_panics_in_debug() {
+        let mut sender: Sender<u32> = Sender::new();
+        let r = sender.mk_receiver();
+        r.inner.state.store(7, Ordering::SeqCst);
+        sender.enqueue(Box::new(1));
+    }
+
+    #[test]
+    fn enqueue_value() {
+        fn round_trip<T: Send + Clone + PartialEq + fmt::Debug + 'static>(x: T) {
+            let mut sender: Sender<T> = Sender::new();
+            let mut r = sender.mk_receiver();
+            let w = r.inner.clone();
+            let th = thread::spawn(move || r.recv_value().unwrap());
+            while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+            assert!(sender.enqueue_value(x.clone()).is_none());
+            let inline = w.slot.load(Ordering::SeqCst) == w.inline_ptr();
+            assert_eq!(inline, Worker::<T>::fits_inline());
+            sender.wake_all();
+            assert_eq!(th.join().unwrap(), x);
+        }
+        assert!(Worker::<(u64, u32)>::fits_inline());
+        round_trip((7u64, 8u32));
+        assert!(!Worker::<[u64; 8]>::fits_inline());
+        round_trip([9u64; 8]);
+    }
+
+    #[test]
+    fn enqueue_value_overflow() {
+        let mut sender: Sender<[u64; 8]> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || *r.recv().unwrap());
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        assert!(sender.enqueue_value([1; 8]).is_none());
+        assert_eq!(sender.enqueue_value([2; 8]), Some([2; 8]));
+        sender.wake_all();
+        assert_eq!(th.join().unwrap(), [1; 8]);
+    }
+
+    #[test]
+    fn recv_into() {
+        let mut sender: Sender<[u64; 512]> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || {
+            let mut buf = [0; 512];
+            let mut firsts = vec![];
+            while r.recv_into(&mut buf).is_ok() {
+                assert!(buf.iter().all(|&x| x == buf[0]));
+                firsts.push(buf[0]);
+            }
+            firsts
+        });
+        for i in 1..11 {
+            while w.state() != Ok(WorkerState::Waiting) { thread::yield_now(); }
+            assert!(sender.enqueue(Box::new([i; 512])).is_none());
+            sender.wake_all();
+        }
+        while w.processed.load(Ordering::SeqCst) < 10 { thread::yield_now(); }
+        ::std::mem::drop(sender);
+        assert_eq!(th.join().unwrap(), (1..11).collect::<Vec<_>>());
+    }
+
+    /// Run a future to completion on the current thread.
+    fn block_on<F: Future>(f: F) -> F::Output {
+        struct Unparker(thread::Thread);
+        impl std::task::Wake for Unparker {
+            fn wake(self: Arc<Self>) { self.0.unpark(); }
+        }
+        let waker = Waker::from(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut f = Box::pin(f);
+        loop {
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(x) => return x,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn async_sender() {
+        const N: u32 = 20;
+        let mut sender: Sender<u32> = Sender::new();
+        let rxs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        let (tx, drain) = AsyncSender::new(sender, 3);
+        for i in 0..3 { block_on(tx.send(Box::new(i))); }
+        // The queue is full, and nobody's draining it yet
+        struct Noop;
+        impl std::task::Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+        let waker = Waker::from(Arc::new(Noop));
+        let mut send = Box::pin(tx.send(Box::new(3)));
+        assert!(send.as_mut().poll(&mut Context::from_waker(&waker)).is_pending());
+        let ths: Vec<_> = rxs.into_iter().map(|mut r| thread::spawn(move || {
+            let mut got = vec![];
+            while let Ok(x) = r.recv() {
+                got.push(*x);
+                sleep_ms(1);
+            }
+            got
+        })).collect();
+        let drain_th = thread::spawn(move || block_on(drain));
+        block_on(send);
+        for i in 4..N { block_on(tx.send(Box::new(i))); }
+        drop(tx);
+        let mut sender = drain_th.join().unwrap();
+        assert_eq!(sender.wait_for_ready(2, None), 2);
+        drop(sender);
+        let mut got: Vec<u32> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        got.sort();
+        assert_eq!(got, (0..N).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn enqueue_async_waits_for_capacity() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || {
+            let a = *r.recv().unwrap();
+            sleep_ms(50);
+            let b = *r.recv().unwrap();
+            (a, b)
+        });
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        block_on(sender.enqueue_async(Box::new(1)));
+        // The only receiver is busy now, so this has to wait for it
+        assert!(sender.enqueue(Box::new(0)).is_some());
+        block_on(sender.enqueue_async(Box::new(2)));
+        assert_eq!(th.join().unwrap(), (1, 2));
+        assert_eq!(sender.registry.ready.waiters.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn trace() {
+        let mut sender: Sender<u32> = Sender::new();
+        let _rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        assert!(sender.take_trace().is_empty());
+        sender.set_tracing(true);
+        sender.set_next_worker(1);
+        for i in 0..3 { assert!(sender.enqueue(Box::new(i)).is_none()); }
+        sender.wake_all();
+        let trace = sender.take_trace();
+        let targets: Vec<_> = trace.iter().filter_map(|e| match *e {
+            TraceEvent::Enqueue { receiver, .. } => Some(receiver),
+            TraceEvent::Wake { .. } => None,
+        }).collect();
+        assert_eq!(targets, vec![1, 2, 0]);
+        match trace[3] {
+            TraceEvent::Wake { receivers, .. } => assert_eq!(receivers, 3),
+            e => panic!("expected a wake, got {:?}", e),
+        }
+        assert!(sender.take_trace().is_empty());
+    }
+
+    #[test]
+    fn adaptive_spin() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        assert_eq!(r.spin_count(), 0);
+        assert!(!r.spin_for_work());
+        r.adaptive_spin(1000);
+        // Frequent bursts: there's always work by the time the receiver starts spinning
+        r.inner.set_state(WorkerState::Pending);
+        for _ in 0..100 { assert!(r.spin_for_work()); }
+        assert_eq!(r.spin_count(), 1000);
+        // Sparse bursts: spinning never finds anything
+        r.inner.set_state(WorkerState::Waiting);
+        for _ in 0..20 { assert!(!r.spin_for_work()); }
+        assert!(r.spin_count() < 100, "spin count {}", r.spin_count());
+        // Back to frequent
+        r.inner.set_state(WorkerState::Pending);
+        for _ in 0..50 { r.spin_for_work(); }
+        assert_eq!(r.spin_count(), 1000);
+    }
+
+    #[test]
+    fn eventfd_balance() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || { r.recv().unwrap(); r });
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        sender.wake_all();
+        let _r = th.join().unwrap();
+        assert_eq!(sender.eventfd_balance(), 0);
+        assert_eq!(sender.drain_eventfd().unwrap(), 0);
+    }
+
+    #[test]
+    fn frozen_sender() {
+        let (mut sender, receivers) = SenderBuilder::new().add_receiver().add_receivers(2).build();
+        let ths: Vec<_> = receivers.into_iter().map(|mut r| {
+            thread::spawn(move || *r.recv().unwrap())
+        }).collect();
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        for i in 0..3 { assert!(sender.enqueue(Box::new(i)).is_none()); }
+        assert!(sender.enqueue(Box::new(3)).is_some());
+        sender.wake_all();
+        let mut got: Vec<u32> = ths.into_iter().map(|th| th.join().unwrap()).collect();
+        got.sort();
+        assert_eq!(got, vec![0, 1, 2]);
+        assert_eq!(sender.counters().sent, 3);
+    }
+
+    #[test]
+    fn try_new_out_of_fds() {
+        // The fd limit is per-process, so do this in a child to avoid upsetting the other tests
+        unsafe {
+            let pid = libc::fork();
+            assert!(pid >= 0);
+            if pid == 0 {
+                let limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+                let ok = libc::setrlimit(libc::RLIMIT_NOFILE, &limit) == 0 &&
+                    match Sender::<u32>::try_new() {
+                        Err(e) => e.raw_os_error() == Some(libc::EMFILE),
+                        Ok(_) => false,
+                    };
+                libc::_exit(if ok { 0 } else { 1 });
+            }
+            let mut status = 0;
+            assert_eq!(libc::waitpid(pid, &mut status, 0), pid);
+            assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+        }
+    }
+
+    #[test]
+    fn enqueue_matching() {
+        #[derive(PartialEq)]
+        enum Kind { Cpu, Gpu }
+        let mut sender: Sender<u32> = Sender::new();
+        let rs = [
+            sender.mk_receiver_tagged(Kind::Cpu),
+            sender.mk_receiver_tagged(Kind::Gpu),
+            sender.mk_receiver(),
+            sender.mk_receiver_tagged(Kind::Gpu),
+        ];
+        park_all(&sender);
+        assert!(rs[1].tag() == Some(&Kind::Gpu));
+        assert!(rs[2].tag::<Kind>().is_none());
+        for i in 0..2 { assert!(sender.enqueue_matching(Box::new(i), |k: &Kind| *k == Kind::Gpu).is_none()); }
+        assert!(sender.enqueue_matching(Box::new(2), |k: &Kind| *k == Kind::Gpu).is_some());
+        // The wrong tag type matches nothing
+        assert!(sender.enqueue_matching(Box::new(3), |_: &u8| true).is_some());
+        let with_work: Vec<bool> = rs.iter().map(has_work).collect();
+        assert_eq!(with_work, vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn spin_only() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver_spin_only();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || {
+            let mut got = vec![];
+            while let Ok(x) = r.recv() { got.push(*x); }
+            got
+        });
+        for i in 0..3 {
+            while w.state() != Ok(WorkerState::Waiting) { thread::yield_now(); }
+            assert!(sender.enqueue(Box::new(i)).is_none());
+            sender.wake_all();
+        }
+        while w.state() != Ok(WorkerState::Waiting) { thread::yield_now(); }
+        assert_eq!(sender.counters().wake_syscalls, 0);
+        assert_eq!(sender.eventfd_balance(), 0);
+        drop(sender);
+        assert_eq!(th.join().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn steal_from() {
+        let mut sender: Sender<u32> = Sender::new();
+        let slow = sender.mk_receiver();
+        let mut thief = sender.mk_receiver();
+        park_all(&sender);
+        thief.inner.set_state(WorkerState::Running);
+        assert!(thief.steal_from(&slow.worker()).is_none());
+        assert!(sender.enqueue(Box::new(7)).is_none());
+        sender.wake_all();
+        assert_eq!(thief.steal_from(&slow.worker()).map(|x| *x), Some(7));
+        assert_eq!(slow.inner.state(), Ok(WorkerState::Waiting));
+        assert_eq!(sender.eventfd_balance(), 0);
+    }
+
+    #[test]
+    fn steal_from_other_sender() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut other: Sender<u32> = Sender::new();
+        let slow = sender.mk_receiver();
+        let mut thief = other.mk_receiver();
+        park_all(&sender);
+        thief.inner.set_state(WorkerState::Running);
+        assert!(sender.enqueue(Box::new(7)).is_none());
+        sender.wake_all();
+        // The wakeup is in the other sender's eventfd, so taking it from ours would block
+        assert!(thief.steal_from(&slow.worker()).is_none());
+        assert_eq!(slow.inner.state(), Ok(WorkerState::Pending));
+        assert_eq!(sender.eventfd_balance(), 1);
+        assert_eq!(other.eventfd_balance(), 0);
+        assert_eq!(*consume(&slow), 7);
+    }
+
+    #[test]
+    fn steal_from_stress() {
+        const N: u32 = 200;
+        let mut sender: Sender<u32> = Sender::new();
+        // Bursts and timestamps give the sender more to do between claiming a receiver and
+        // handing it the payload; a thief mustn't get in during that time
+        sender.set_timestamps(true);
+        let burst = sender.begin_burst();
+        let mut slow = sender.mk_receiver();
+        let victim = slow.worker();
+        let done = Arc::new(AtomicBool::new(false));
+        let hammer = {
+            let done = done.clone();
+            thread::spawn(move || while !done.load(Ordering::SeqCst) { burst.remaining(); })
+        };
+        let thieves: Vec<_> = (0..2).map(|_| {
+            let mut thief = sender.mk_receiver();
+            let (victim, done) = (victim.clone(), done.clone());
+            thread::spawn(move || {
+                let mut got = vec![];
+                while !done.load(Ordering::SeqCst) {
+                    match thief.steal_from(&victim) {
+                        Some(x) => got.push(*x),
+                        None => thread::yield_now(),
+                    }
+                }
+                got
+            })
+        }).collect();
+        let slow_th = thread::spawn(move || {
+            let mut got = vec![];
+            while let Ok(x) = slow.recv() {
+                got.push(*x);
+                thread::sleep(Duration::from_micros(200));
+            }
+            got
+        });
+        for i in 0..N {
+            let mut x = Box::new(i);
+            while let Some(y) = sender.enqueue(x) { x = y; thread::yield_now(); }
+            sender.wake_all();
+        }
+        while victim.state() != Ok(WorkerState::Waiting) { thread::yield_now(); }
+        done.store(true, Ordering::SeqCst);
+        hammer.join().unwrap();
+        let mut got: Vec<u32> = thieves.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        assert_eq!(sender.eventfd_balance(), 0);
+        drop(sender);
+        got.extend(slow_th.join().unwrap());
+        got.sort();
+        assert_eq!(got, (0..N).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ordered() {
+        let mut sender: Sender<u32> = Sender::new();
+        sender.set_ordered(true);
+        let log = Arc::new(Mutex::new(vec![]));
+        let ths: Vec<_> = (0..3u64).map(|i| {
+            let mut r = sender.mk_receiver();
+            let log = log.clone();
+            thread::spawn(move || {
+                while let Ok(x) = r.recv() {
+                    log.lock().unwrap().push(*x);
+                    // The receivers which get the earlier payloads are the slowest
+                    sleep_ms(3 - i);
+                }
+            })
+        }).collect();
+        for burst in 0..10 {
+            while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+            sender.set_next_worker(0);
+            for i in 0..3 { assert!(sender.enqueue(Box::new(burst * 3 + i)).is_none()); }
+            sender.wake_all();
+        }
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        drop(sender);
+        for th in ths { th.join().unwrap(); }
+        assert_eq!(*log.lock().unwrap(), (0..30).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ordered_skips_taken_back_payloads() {
+        let mut sender: Sender<u32> = Sender::new();
+        sender.set_ordered(true);
+        let _rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        let token = sender.enqueue_with_token(Box::new(0)).unwrap();
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        assert_eq!(sender.cancel(token).map(|x| *x), Some(0));
+        // Payload 1 doesn't have to wait for payload 0 any more
+        sender.registry.sequencer.wait_turn(1);
+    }
+
+    #[test]
+    fn ordered_skips_expired_payloads() {
+        let mut sender: Sender<u32> = Sender::new();
+        sender.set_ordered(true);
+        let mut r = sender.mk_receiver();
+        let th = thread::spawn(move || { assert_eq!(r.recv_once(), Ok(None)); r });
+        while sender.workers[0].state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        let deadline = Instant::now() + Duration::from_millis(1);
+        assert!(sender.enqueue_deadline(Box::new(0), deadline).is_none());
+        sleep_ms(5);
+        sender.wake_all();
+        let r = th.join().unwrap();
+        assert_eq!(r.expired(), 1);
+        // The receiver discarded payload 0 without going back to `recv`, so it mustn't hold up
+        // payload 1
+        sender.registry.sequencer.wait_turn(1);
+    }
+
+    #[test]
+    fn on_worker_ready() {
+        let mut sender: Sender<u32> = Sender::new();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen2 = seen.clone();
+        sender.on_worker_ready(move |id| seen2.lock().unwrap().push(id));
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        let mut ids: Vec<usize> = rs.iter().map(|r| r.id()).collect();
+        let ths: Vec<_> = rs.into_iter().map(|mut r| thread::spawn(move || r.recv().map(|x| *x))).collect();
+        while seen.lock().unwrap().len() < 3 { sleep_ms(1); }
+        sleep_ms(10);
+        let mut seen_ids = seen.lock().unwrap().clone();
+        seen_ids.sort();
+        ids.sort();
+        assert_eq!(seen_ids, ids);
+        drop(sender);
+        for th in ths { assert!(th.join().unwrap().is_err()); }
+    }
+
+    #[test]
+    fn recv_with_generation() {
+        let mut sender: Sender<u32> = Sender::new();
+        let ths: Vec<_> = (0..2).map(|_| {
+            let mut r = sender.mk_receiver();
+            thread::spawn(move || {
+                let mut got = vec![];
+                while let Ok((x, generation)) = r.recv_with_generation() { got.push((*x, generation)); }
+                got
+            })
+        }).collect();
+        for burst in 0..3 {
+            while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+            for i in 0..2 { assert!(sender.enqueue(Box::new(burst * 2 + i)).is_none()); }
+            sender.wake_all();
+        }
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        drop(sender);
+        let mut got: Vec<(u32, u64)> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        got.sort();
+        assert_eq!(got, vec![(0, 0), (1, 0), (2, 1), (3, 1), (4, 2), (5, 2)]);
+    }
+
+    #[test]
+    fn custom_wait_strategy() {
+        #[derive(Default)]
+        struct CountingSpin(Mutex<Vec<usize>>);
+        impl WaitStrategy for CountingSpin {
+            fn wait(&self, state: &AtomicUsize, expected: usize) {
+                while state.load(Ordering::SeqCst) == expected { thread::yield_now(); }
+            }
+            fn notify(&self, n: usize) { self.0.lock().unwrap().push(n); }
+        }
+        let strategy = Arc::new(CountingSpin::default());
+        let mut sender: Sender<u32> = Sender::new();
+        let ths: Vec<_> = (0..2).map(|_| {
+            let mut r = sender.mk_receiver_with_wait(strategy.clone());
+            thread::spawn(move || *r.recv().unwrap())
+        }).collect();
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        for i in 0..2 { assert!(sender.enqueue(Box::new(i)).is_none()); }
+        sender.wake_all();
+        let mut got: Vec<u32> = ths.into_iter().map(|th| th.join().unwrap()).collect();
+        got.sort();
+        assert_eq!(got, vec![0, 1]);
+        assert_eq!(*strategy.0.lock().unwrap(), vec![2]);
+        assert_eq!(sender.counters().wake_syscalls, 0);
+    }
+
+    #[test]
+    fn cancel_burst() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        // Two payloads from a burst which has been woken, and one which hasn't
+        for i in 0..2 { assert!(sender.enqueue(Box::new(i)).is_none()); }
+        sender.wake_all();
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        let mut xs: Vec<u32> = sender.cancel_burst().into_iter().map(|x| *x).collect();
+        xs.sort();
+        assert_eq!(xs, vec![0, 1, 2]);
+        assert!(rs.iter().all(|r| !has_work(r) && r.inner.state() == Ok(WorkerState::Waiting)));
+        assert_eq!(sender.workers_to_unblock, 0);
+        assert_eq!(sender.eventfd_balance(), 0);
+        sender.wake_all();
+        // The receivers have nothing to wake up for
+        assert!(!sender.eventfd.is_readable().unwrap());
+    }
+
+    #[test]
+    fn sender_writer() {
+        use std::io::Write;
+        let mut sender: Sender<Vec<u8>> = Sender::new();
+        sender.set_ordered(true);
+        let out = Arc::new(Mutex::new(vec![]));
+        let ths: Vec<_> = (0..2).map(|_| {
+            let mut r = sender.mk_receiver();
+            let out = out.clone();
+            thread::spawn(move || {
+                while let Ok(chunk) = r.recv() {
+                    assert!(chunk.len() <= 16);
+                    out.lock().unwrap().extend_from_slice(&chunk);
+                }
+            })
+        }).collect();
+        let input: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = SenderWriter::new(sender, 16);
+        let mut rest = &input[..];
+        while !rest.is_empty() {
+            match writer.write(&rest[..cmp::min(rest.len(), 7)]) {
+                Ok(n) => rest = &rest[n..],
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+                Err(e) => panic!("{}", e),
+            }
+        }
+        loop {
+            match writer.flush() {
+                Ok(()) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+                Err(e) => panic!("{}", e),
+            }
+        }
+        let sender = writer.into_inner();
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        drop(sender);
+        for th in ths { th.join().unwrap(); }
+        assert_eq!(*out.lock().unwrap(), input);
+    }
+
+    #[test]
+    fn enqueue_or_spill() {
+        let mut sender: Sender<u32> = Sender::new();
+        sender.set_spill_capacity(2);
+        let mut rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        // Fast path: straight into a waiting receiver's slot
+        rs[0].inner.set_state(WorkerState::Waiting);
+        assert!(sender.enqueue_or_spill(Box::new(0)).is_none());
+        assert!(has_work(&rs[0]));
+        assert_eq!(sender.registry.spill.len.load(Ordering::SeqCst), 0);
+        // Nobody else is ready, so these spill, until the queue is full
+        assert!(sender.enqueue_or_spill(Box::new(1)).is_none());
+        assert!(sender.enqueue_or_spill(Box::new(2)).is_none());
+        assert_eq!(sender.enqueue_or_spill(Box::new(3)).map(|x| *x), Some(3));
+        assert_eq!(sender.counters().dropped, 1);
+        // A receiver which becomes ready gets the oldest spilled payload, ahead of the new one
+        rs[1].inner.set_state(WorkerState::Waiting);
+        assert!(sender.enqueue_or_spill(Box::new(4)).is_none());
+        let x = consume(&rs[1]);
+        assert_eq!(*x, 1);
+        // A busy receiver coming back to recv takes from the queue, in order, without waiting
+        rs[1].inner.set_state(WorkerState::Running);
+        assert_eq!(*rs[1].recv().unwrap(), 2);
+        assert_eq!(*rs[1].recv().unwrap(), 4);
+        assert_eq!(sender.registry.spill.len.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn spawn_cancellable() {
+        let started = AtomicBool::new(false);
+        let start = Instant::now();
+        scoped_channel(|chan: &mut ChannelScope<u32>| {
+            chan.spawn_cancellable(|_r, cancel| {
+                // Busy with something which never goes back to recv
+                started.store(true, Ordering::SeqCst);
+                while !cancel.is_cancelled() { sleep_ms(1); }
+            });
+            while !started.load(Ordering::SeqCst) { sleep_ms(1); }
+        });
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn drive() {
+        const PERIOD: Duration = Duration::from_millis(10);
+        let mut sender: Sender<u32> = Sender::new();
+        let mut times = vec![];
+        let start = Instant::now();
+        let unplaced = sender.drive(|| {
+            times.push(start.elapsed());
+            // Slow enough that the bursts would drift if this wasn't accounted for
+            thread::sleep(Duration::from_millis(8));
+            vec![Box::new(times.len() as u32)]
+        }, PERIOD, 5);
+        // There are no receivers, so everything comes back
+        assert_eq!(unplaced.into_iter().map(|x| *x).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(times.len(), 5);
+        for (n, &t) in times.iter().enumerate() {
+            assert!(t >= PERIOD * n as u32, "burst {} at {:?}", n, t);
+        }
+        assert!(times[4] < Duration::from_millis(60), "{:?}", times);
+
+        // The first burst goes straight away, however long the period
+        let start = Instant::now();
+        assert_eq!(sender.drive(|| vec![Box::new(0)], Duration::MAX, 1).len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn custom_selector() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..4).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        // Always the highest-numbered waiting receiver
+        sender.set_selector(|states| states.iter().rposition(|&s| s == WorkerState::Waiting));
+        rs[2].inner.set_state(WorkerState::Running);
+        for i in 0..3 { assert!(sender.enqueue(Box::new(i)).is_none()); }
+        assert!(sender.enqueue(Box::new(3)).is_some());
+        let placed: Vec<_> = rs.iter().map(|r| r.inner.slot_take().map(|x| unsafe { *Box::from_raw(x) })).collect();
+        assert_eq!(placed, vec![Some(2), Some(1), None, Some(0)]);
+
+        // Choosing a busy receiver refuses the payload
+        park_all(&sender);
+        rs[0].inner.set_state(WorkerState::Running);
+        sender.set_selector(|_| Some(0));
+        assert!(sender.enqueue(Box::new(4)).is_some());
+        assert!(rs.iter().all(|r| !has_work(r)));
+
+        // So does choosing one which doesn't exist
+        sender.set_selector(|states| Some(states.len()));
+        assert!(sender.enqueue(Box::new(4)).is_some());
+        assert!(rs.iter().all(|r| !has_work(r)));
+
+        // And back to round-robin
+        sender.clear_selector();
+        assert!(sender.enqueue(Box::new(5)).is_none());
+        assert!(!has_work(&rs[0]));
+        park_all(&sender);
+    }
+
+    #[test]
+    fn batch_wakes_once() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        let generation = sender.registry.generation.load(Ordering::SeqCst);
+
+        // An empty batch doesn't make a syscall
+        drop(sender.batch());
+        sender.batch().commit().unwrap();
+        assert_eq!(sender.counters().wake_syscalls, 0);
+        assert_eq!(sender.registry.generation.load(Ordering::SeqCst), generation);
+
+        {
+            let mut batch = sender.batch();
+            assert!(batch.enqueue(Box::new(1)).is_none());
+            assert!(batch.enqueue(Box::new(2)).is_none());
+            assert_eq!(batch.counters().wake_syscalls, 0);
+        }
+        assert_eq!(sender.counters().wake_syscalls, 1);
+        assert_eq!(sender.registry.generation.load(Ordering::SeqCst), generation + 1);
+        let mut got: Vec<u32> = rs.iter_mut().map(|r| *r.recv().unwrap()).collect();
+        got.sort();
+        assert_eq!(got, vec![1, 2]);
+    }
+
+    #[test]
+    fn burst_channel() {
+        const N: usize = 30;
+        let got = Arc::new(Mutex::new(vec![]));
+        let mut chan = BurstChannel::new();
+        for i in 0..3 {
+            let got = got.clone();
+            chan.spawn(move |x: Box<usize>| got.lock().unwrap().push((i, *x)));
+        }
+        assert_eq!(chan.len(), 3);
+        let mut next = 0;
+        while next < N {
+            chan.wait_for_ready(1, None);
+            while next < N && chan.enqueue(Box::new(next)).is_none() { next += 1; }
+            chan.wake_all();
+        }
+        chan.wait_for_ready(3, None);
+        drop(chan);
+        let mut got = Arc::try_unwrap(got).unwrap().into_inner().unwrap();
+        got.sort_by_key(|&(_, x)| x);
+        assert_eq!(got.iter().map(|&(_, x)| x).collect::<Vec<_>>(), (0..N).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn burst_channel_drops_unreceived() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Counted;
+        impl Drop for Counted {
+            fn drop(&mut self) { DROPS.fetch_add(1, Ordering::SeqCst); }
+        }
+        let received = Arc::new(AtomicUsize::new(0));
+        let mut chan = BurstChannel::new();
+        let r = received.clone();
+        chan.spawn(move |_: Box<Counted>| { r.fetch_add(1, Ordering::SeqCst); });
+        chan.wait_for_ready(1, None);
+        // Never woken, so never received
+        assert!(chan.enqueue(Box::new(Counted)).is_none());
+        drop(chan);
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn wakeup_latency() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || {
+            while r.recv().is_ok() {}
+            r.wakeup_latency().clone()
+        });
+        for i in 0..50 {
+            while w.state() != Ok(WorkerState::Waiting) { thread::yield_now(); }
+            assert!(sender.enqueue(Box::new(i)).is_none());
+            sender.wake_all();
+        }
+        while w.state() != Ok(WorkerState::Waiting) { thread::yield_now(); }
+        drop(sender);
+        let hist = th.join().unwrap();
+        assert_eq!(hist.count(), 50);
+        let mean = hist.mean().unwrap();
+        assert!(mean > Duration::from_nanos(0) && mean < Duration::from_secs(1), "{:?}", mean);
+    }
+
+    #[test]
+    fn enqueue_ttl() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut rs: Vec<_> = (0..8).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        assert_eq!(sender.enqueue_ttl(Box::new(0), 0).map(|x| *x), Some(0));
+        assert!(sender.enqueue_ttl(Box::new(0), 3).is_none());
+        sender.wake_all();
+        let mut refused = 0;
+        // Each payload fans out into two more, until the budget runs out
+        while let Some(r) = rs.iter_mut().find(|r| has_work(r)) {
+            let (x, ttl) = r.recv_with_ttl().unwrap();
+            r.inner.set_state(WorkerState::Waiting);
+            let ttl = ttl.unwrap();
+            for i in 0..2 {
+                match sender.enqueue_ttl(Box::new(*x * 2 + 1 + i), ttl - 1) {
+                    Some(_) => { assert_eq!(ttl, 1); refused += 1; }
+                    None => assert!(ttl > 1),
+                }
+            }
+            sender.wake_all();
+        }
+        assert_eq!(sender.counters().sent, 1 + 2 + 4);
+        assert_eq!(refused, 8);
+    }
+
+    #[test]
+    fn stage() {
+        let mut sender: Sender<u32> = Sender::new();
+        for i in 0..3 { sender.stage(Box::new(i)); }
+        assert_eq!(sender.flush_staged(), 3);
+        let ths: Vec<_> = (0..2).map(|_| {
+            let mut r = sender.mk_receiver();
+            thread::spawn(move || {
+                let mut got = vec![];
+                while let Ok(x) = r.recv() { got.push(*x); }
+                got
+            })
+        }).collect();
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        assert_eq!(sender.flush_staged(), 1);
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        assert_eq!(sender.flush_staged(), 0);
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        drop(sender);
+        let mut got: Vec<u32> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        got.sort();
+        assert_eq!(got, vec![0, 1, 2]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn check_invariants() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || {
+            let mut got = vec![];
+            while let Ok(x) = r.recv() { got.push(*x); }
+            got
+        });
+        for i in 0..50 {
+            let mut x = Box::new(i);
+            while let Some(y) = sender.enqueue(x) { x = y; thread::yield_now(); }
+            sender.wake_all();
+            assert_eq!(sender.check_invariants(), Ok(()));
+        }
+        while w.state() != Ok(WorkerState::Waiting) { thread::yield_now(); }
+        assert_eq!(sender.check_invariants(), Ok(()));
+
+        // Corrupt the state while the receiver's blocked: it won't notice, because nothing's
+        // written to the eventfd
+        w.set_state(WorkerState::Pending);
+        assert_eq!(sender.check_invariants(), Err(InvariantViolation::PendingWithoutPayload { receiver: 0 }));
+        w.state.store(7, Ordering::SeqCst);
+        assert_eq!(sender.check_invariants(), Err(InvariantViolation::BadState { receiver: 0, state: 7 }));
+        w.set_state(WorkerState::Waiting);
+        sender.eventfd.1.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(sender.check_invariants(), Err(InvariantViolation::EventFdBalance { balance: 1, busy: 0 }));
+        sender.eventfd.1.fetch_sub(1, Ordering::SeqCst);
+        assert_eq!(sender.check_invariants(), Ok(()));
+        drop(sender);
+        assert_eq!(th.join().unwrap(), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn history() {
+        use WorkerState::*;
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver().with_history(4);
+        let mut other = sender.mk_receiver();
+        park_all(&sender);
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        sender.wake_all();
+        assert_eq!(*r.recv().unwrap(), 1);
+        // r is busy, so this one's for the other receiver
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        sender.wake_all();
+        assert!(r.recv_once().unwrap().is_none());
+        assert_eq!(*other.recv().unwrap(), 2);
+        drop(sender);
+        assert!(r.recv().is_err());
+        assert_eq!(r.history(), vec![
+            // (Op::Wait, Pending) has been forgotten
+            (Op::Wake, Running),
+            (Op::Wait, Waiting),
+            (Op::Wake, Waiting),
+            (Op::Wait, Orphaned),
+        ]);
+        assert!(other.history().is_empty());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "History: [(Wait, Orphaned)]")]
+    fn history_in_bad_state() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver().with_history(4);
+        drop(sender);
+        assert!(r.recv().is_err());
+        r.inner.state.store(7, Ordering::SeqCst);
+        let _ = r.recv();
+    }
+
+    #[test]
+    fn multi_sender() {
+        let a: Sender<u32> = Sender::new();
+        let b = a.reuse_eventfd::<u32>();
+        let mut multi = MultiSender::new(vec![a, b, Sender::new()]);
+        let ths: Vec<Vec<_>> = multi.senders().iter_mut().map(|sender| {
+            (0..2).map(|_| {
+                let mut r = sender.mk_receiver();
+                thread::spawn(move || r.recv().ok().map(|x| *x))
+            }).collect()
+        }).collect();
+        for sender in multi.senders() {
+            while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        }
+        let work = (0..3u32).map(|i| (0..3).map(|j| Box::new(i * 10 + j)).collect()).collect();
+        let rejected = multi.dispatch(work);
+        let rejected: Vec<Vec<u32>> = rejected.into_iter().map(|xs| xs.into_iter().map(|x| *x).collect()).collect();
+        assert_eq!(rejected, vec![vec![2], vec![12], vec![22]]);
+        for (i, ths) in ths.into_iter().enumerate() {
+            let mut got: Vec<u32> = ths.into_iter().map(|th| th.join().unwrap().unwrap()).collect();
+            got.sort();
+            assert_eq!(got, vec![i as u32 * 10, i as u32 * 10 + 1]);
+        }
+        let syscalls: Vec<u64> = multi.senders().iter().map(|s| s.counters().wake_syscalls).collect();
+        assert_eq!(syscalls, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn enqueue_fn() {
+        let mut sender: Sender<thread::ThreadId> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let w = r.inner.clone();
+        let th = thread::spawn(move || (*r.recv().unwrap(), thread::current().id()));
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        assert!(sender.enqueue_fn(|| thread::current().id()).is_none());
+        assert!(sender.enqueue_fn(|| thread::current().id()).is_some());
+        sender.wake_all();
+        let (made_on, receiver) = th.join().unwrap();
+        assert_eq!(made_on, receiver);
+        assert!(made_on != thread::current().id());
+    }
+
+    #[test]
+    fn inline_payloads_can_be_drained() {
+        let mut sender: Sender<String> = Sender::new();
+        let r = sender.mk_receiver();
+        park_all(&sender);
+        assert!(sender.enqueue_value("hello".to_string()).is_none());
+        assert_eq!(r.inner.slot.load(Ordering::SeqCst), r.inner.inline_ptr());
+        assert!(sender.enqueue_value("world".to_string()).is_some());
+        assert_eq!(sender.drain_unwoken().into_iter().map(|x| *x).collect::<Vec<_>>(), vec!["hello"]);
+    }
+
+    #[test]
+    fn reserve() {
+        let mut sender: Sender<u32> = Sender::new();
+        sender.reserve(10);
+        let capacity = |sender: &Sender<u32>| {
+            let registry = sender.registry.workers.lock().unwrap();
+            (registry.capacity(), registry.as_ptr(), sender.workers.capacity(), sender.workers.as_ptr())
+        };
+        let before = capacity(&sender);
+        assert!(before.0 >= 10 && before.2 >= 10);
+        let _rs: Vec<_> = (0..10).map(|_| sender.mk_receiver()).collect();
+        assert_eq!(sender.workers.len(), 10);
+        assert_eq!(capacity(&sender), before);
+    }
+
+    #[test]
+    fn mk_receiver_per_core() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs = sender.mk_receiver_per_core();
+        let expected = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        assert_eq!(rs.len(), expected);
+        assert_eq!(sender.workers.len(), expected);
+    }
+
+    #[cfg(not(feature = "epoll"))]
+    #[test]
+    fn spurious_backoff() {
+        // A non-zero initial value means the receiver will never stop being woken up
+        let config = SenderConfig { initial: 1, ..SenderConfig::default() };
+        let mut sender: Sender<u32> = Sender::with_config(config);
+        let mut receiver = sender.mk_receiver();
+        let worker = sender.workers[0].clone();
+        receiver.set_spurious_backoff(3, Duration::from_millis(1));
+        let th = thread::spawn(move || {
+            let x = receiver.recv().unwrap();
+            (*x, receiver.spurious_wakeups())
+        });
+        sleep_ms(50);
+
+        // A busy loop would rack up far more than this
+        let spurious = worker.spurious.load(Ordering::Relaxed);
+        assert!((3..1000).contains(&spurious), "spurious = {}", spurious);
+
+        assert!(sender.enqueue(Box::new(7)).is_none());
+        sender.wake_all();
+        let (x, total) = th.join().unwrap();
+        assert_eq!(x, 7);
+        assert!(total >= spurious);
+    }
+
+    #[test]
+    fn debug() {
+        struct NotDebug;
+        let mut sender: Sender<NotDebug> = Sender::new();
+        let rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        assert!(sender.enqueue(Box::new(NotDebug)).is_none());
+
+        let s = format!("{:?}", sender);
+        assert!(s.starts_with("Sender<"), "{}", s);
+        assert!(s.contains("NotDebug"), "{}", s);
+        assert!(s.contains("receivers: 2"), "{}", s);
+        assert!(s.contains("workers_to_unblock: 1"), "{}", s);
+        assert!(format!("{:?}", rs[0]).contains("state: \"pending\""));
+        assert!(format!("{:?}", rs[1]).contains("state: \"waiting\""));
+    }
+
+    #[test]
+    fn scoped_channel_borrows() {
+        let data: Vec<u8> = (0..100).collect();
+        let seen = AtomicUsize::new(0);
+        let total = AtomicUsize::new(0);
+        scoped_channel(|chan| {
+            for _ in 0..2 {
+                chan.spawn(|mut receiver: Receiver<&[u8]>| {
+                    while let Ok(xs) = receiver.recv() {
+                        let sum: usize = xs.iter().map(|&x| x as usize).sum();
+                        total.fetch_add(sum, Ordering::SeqCst);
+                        seen.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
+            }
+            sleep_ms(10);
+            assert!(chan.enqueue(Box::new(&data[..50])).is_none());
+            assert!(chan.enqueue(Box::new(&data[50..])).is_none());
+            chan.wake_all();
+            while seen.load(Ordering::SeqCst) < 2 { thread::yield_now(); }
+        });
+        assert_eq!(total.into_inner(), (0..100).sum());
+    }
+
+    #[test]
+    fn wake_all_would_block() {
+        let config = SenderConfig { nonblock: true, ..SenderConfig::default() };
+        let mut sender: Sender<u32> = Sender::with_config(config);
+        let _receiver = sender.mk_receiver();
+        park_all(&sender);
+
+        // Push the counter up to its maximum value
+        let mut buf = [0; 8];
+        NativeEndian::write_u64(&mut buf, u64::MAX - 1);
+        sys_write(sender.eventfd.0, &buf).unwrap();
+
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        let err = sender.try_wake_all().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(sender.workers_to_unblock, 1);
+    }
+
+    #[test]
+    fn wake_all_skips_syscall_when_idle() {
+        let mut sender: Sender<u32> = Sender::new();
+        let _rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+
+        sender.wake_all();
+        assert_eq!(sender.counters().wake_syscalls, 0);
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        assert!(sender.enqueue(Box::new(2)).is_none());
+        assert_eq!(sender.drain_unwoken().len(), 2);
+        sender.wake_all();
+        assert_eq!(sender.counters().wake_syscalls, 0);
+        assert_eq!(sender.drain_eventfd().unwrap(), 0);
+
+        assert!(sender.enqueue(Box::new(3)).is_none());
+        sender.wake_all();
+        assert_eq!(sender.counters().wake_syscalls, 1);
+    }
+
+    #[test]
+    fn orphaning_leaves_no_wakeups() {
+        let mut sender: Sender<u32> = Sender::new();
+        let threads: Vec<_> = (0..3).map(|_| {
+            let mut receiver = sender.mk_receiver();
+            thread::spawn(move || {
+                assert_eq!(receiver.recv(), Err(RecvError::Orphaned));
+                receiver
+            })
+        }).collect();
+        let idle = sender.mk_receiver();
+        sleep_ms(10);
+        // Leave one payload unwoken as well
+        assert!(sender.enqueue(Box::new(1)).is_none());
+
+        ::std::mem::drop(sender);
+        let receivers: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        // Nothing left in the eventfd to wake up anyone who polls it now
+        assert!(!sys_poll(idle.eventfd.0, 0).unwrap());
+        ::std::mem::drop(receivers);
+    }
+
+    #[test]
+    fn orphaned_while_parked() {
+        // A receiver left WAITING by `recv_once` takes back the wakeup the sender leaves for it,
+        // whether it finds out it's been orphaned from `recv` or by being dropped
+        for &dropped in &[false, true] {
+            let mut sender: Sender<u32> = Sender::new();
+            let mut other = sender.reuse_eventfd::<u32>();
+            let target = sender.mk_receiver();
+            let mut r = sender.mk_receiver();
+            target.inner.set_state(WorkerState::Waiting);
+            let th = thread::spawn(move || { assert_eq!(r.recv_once(), Ok(None)); r });
+            while sender.workers[1].state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+            sender.set_next_worker(0);
+            assert!(sender.enqueue(Box::new(1)).is_none());
+            sender.wake_all();
+            let mut r = th.join().unwrap();
+            assert_eq!(*consume(&target), 1);
+            drop(target);
+            assert_eq!(sender.drain_eventfd().unwrap(), 1);
+
+            drop(sender);
+            if dropped { drop(r); } else { assert_eq!(r.recv(), Err(RecvError::Orphaned)); }
+            let fresh = other.mk_receiver();
+            assert!(!sys_poll(fresh.eventfd.0, 0).unwrap());
+        }
+    }
+
+    #[test]
+    fn orphaned_while_stolen_from() {
+        // The sender goes away while a thief has the parked receiver LOCKED. The thief takes the
+        // payload's wakeup, so the sender must leave another one for the receiver itself.
+        let mut sender: Sender<u32> = Sender::new();
+        let mut other = sender.reuse_eventfd::<u32>();
+        let target = sender.mk_receiver();
+        let mut r = sender.mk_receiver();
+        target.inner.set_state(WorkerState::Waiting);
+        let th = thread::spawn(move || { assert_eq!(r.recv_once(), Ok(None)); r });
+        while sender.workers[1].state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        sender.set_next_worker(0);
+        assert!(sender.enqueue(Box::new(0)).is_none());
+        sender.wake_all();
+        let mut r = th.join().unwrap();
+        assert_eq!(*consume(&target), 0);
+        drop(target);
+        assert_eq!(sender.drain_eventfd().unwrap(), 1);
+
+        assert!(sender.enqueue(Box::new(1)).is_none());
+        sender.wake_all();
+        let victim = r.worker();
+        assert_eq!(victim.cas_state(WorkerState::Pending, WorkerState::Locked), Ok(WorkerState::Pending));
+
+        drop(sender);
+        // ...and the thief finishes up as `steal_from` would
+        assert_eq!(victim.cas_state(WorkerState::Locked, WorkerState::Waiting), Ok(WorkerState::Orphaned));
+        assert_eq!(victim.slot_take().map(|x| *unsafe { victim.unpack_box(x) }), Some(1));
+        r.eventfd.read().unwrap();
+        let fresh = other.mk_receiver();
+        assert!(sys_poll(fresh.eventfd.0, 0).unwrap());
+        assert_eq!(r.recv(), Err(RecvError::Orphaned));
+        assert!(!sys_poll(fresh.eventfd.0, 0).unwrap());
+    }
+
+    #[test]
+    fn drop_timeout() {
+        let mut sender: Sender<u32> = Sender::new();
+        let threads: Vec<_> = (0..3).map(|_| {
+            let mut receiver = sender.mk_receiver();
+            thread::spawn(move || {
+                assert_eq!(receiver.recv(), Err(RecvError::Orphaned));
+                receiver
+            })
+        }).collect();
+        let workers = sender.workers.clone();
+        while workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+
+        sender.set_drop_timeout(Duration::from_secs(5));
+        ::std::mem::drop(sender);
+        assert!(workers.iter().all(|w| w.orphan_seen.load(Ordering::SeqCst)));
+
+        // The receivers share ownership of the eventfd, so it outlives the sender
+        let receivers: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        for r in &receivers {
+            assert!(unsafe { libc::fcntl(r.eventfd.0, libc::F_GETFD) } >= 0);
+        }
+    }
+
+    #[test]
+    fn send_blocking() {
+        let shared: SharedSender<u32> = SharedSender::new(Sender::new());
+        let mut r = shared.mk_receiver();
+        let (got_tx, got_rx) = std::sync::mpsc::channel();
+        let (gate_tx, gate_rx) = std::sync::mpsc::channel::<()>();
+        let w = r.inner.clone();
+        let receiver = thread::spawn(move || {
+            while let Ok(x) = r.recv() {
+                got_tx.send(*x).unwrap();
+                if gate_rx.recv().is_err() { break; }
+            }
+        });
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        assert!(shared.send(Box::new(1)).is_none());
+        assert_eq!(got_rx.recv().unwrap(), 1);
+
+        // The receiver is busy, so this producer has to wait...
+        let done = Arc::new(AtomicBool::new(false));
+        let a = {
+            let (shared, done) = (shared.clone(), done.clone());
+            thread::spawn(move || { shared.send_blocking(Box::new(2)); done.store(true, Ordering::SeqCst); })
+        };
+        sleep_ms(20);
+        assert!(!done.load(Ordering::SeqCst));
+        // ...but the other one isn't held up
+        let b = {
+            let shared = shared.clone();
+            thread::spawn(move || (0..100).filter(|&i| shared.send(Box::new(i)).is_some()).count())
+        };
+        assert_eq!(b.join().unwrap(), 100);
+        assert!(!done.load(Ordering::SeqCst));
+
+        // Once the receiver is free, the waiting producer gets through
+        gate_tx.send(()).unwrap();
+        a.join().unwrap();
+        assert_eq!(got_rx.recv().unwrap(), 2);
+        ::std::mem::drop(gate_tx);
+        ::std::mem::drop(shared);
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn send_blocking_during_wait_for_ready() {
+        let shared: SharedSender<u32> = SharedSender::new(Sender::new());
+        let mut r = shared.mk_receiver();
+        let (got_tx, got_rx) = std::sync::mpsc::channel();
+        let (gate_tx, gate_rx) = std::sync::mpsc::channel::<()>();
+        let w = r.inner.clone();
+        let receiver = thread::spawn(move || {
+            while let Ok(x) = r.recv() {
+                got_tx.send(*x).unwrap();
+                if gate_rx.recv().is_err() { break; }
+            }
+        });
+        while w.state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        assert!(shared.send(Box::new(1)).is_none());
+        assert_eq!(got_rx.recv().unwrap(), 1);
+
+        // One thread waits for the receiver while holding the sender's lock, and another blocks
+        // on that lock from inside `send_blocking`. Neither may stop the receiver from telling
+        // them it's free.
+        let b = {
+            let shared = shared.clone();
+            thread::spawn(move || shared.lock().wait_for_ready(1, None))
+        };
+        sleep_ms(20);
+        let a = {
+            let shared = shared.clone();
+            thread::spawn(move || shared.send_blocking(Box::new(2)))
+        };
+        sleep_ms(20);
+        gate_tx.send(()).unwrap();
+        assert_eq!(b.join().unwrap(), 1);
+        a.join().unwrap();
+        assert_eq!(got_rx.recv().unwrap(), 2);
+        ::std::mem::drop(gate_tx);
+        ::std::mem::drop(shared);
+        receiver.join().unwrap();
+    }
+
+    #[test]
+    fn into_retyped() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (retype_tx, retype_rx) = std::sync::mpsc::channel();
+        let th = thread::spawn(move || {
+            while let Ok(x) = r.recv() { tx.send(x.to_string()).unwrap(); }
+            let retype: Box<dyn Fn(Receiver<u32>) -> Receiver<String> + Send> = retype_rx.recv().unwrap();
+            let mut r = retype(r);
+            while let Ok(x) = r.recv() { tx.send(*x).unwrap(); }
+        });
+        while sender.enqueue(Box::new(1)).is_some() { sleep_ms(1); }
+        sender.wake_all();
+        assert_eq!(rx.recv().unwrap(), "1");
+        while sender.can_enqueue().is_none() { sleep_ms(1); }
+
+        let fd = sender.eventfd.0;
+        let (mut sender, retype) = sender.into_retyped::<String>();
+        assert_eq!(sender.eventfd.0, fd);
+        retype_tx.send(Box::new(retype)).unwrap();
+        let mut x = Box::new("hello".to_string());
+        while let Some(y) = sender.enqueue(x) { x = y; sleep_ms(1); }
+        sender.wake_all();
+        assert_eq!(rx.recv().unwrap(), "hello");
+        ::std::mem::drop(sender);
+        th.join().unwrap();
+    }
+
+    #[test]
+    fn wait_for_ready() {
+        let mut sender: Sender<u32> = Sender::new();
+        let start = Instant::now();
+        let threads: Vec<_> = (0..3).map(|i| {
+            let mut r = sender.mk_receiver();
+            thread::spawn(move || {
+                sleep_ms(20 * i);
+                r.recv()
+            })
+        }).collect();
+        assert_eq!(sender.wait_for_ready(3, None), 3);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert!(sender.workers.iter().all(|w| w.state() == Ok(WorkerState::Waiting)));
+
+        let start = Instant::now();
+        assert_eq!(sender.wait_for_ready(4, Some(Duration::from_millis(20))), 3);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        ::std::mem::drop(sender);
+        for th in threads { assert_eq!(th.join().unwrap(), Err(RecvError::Orphaned)); }
+    }
+
+    #[test]
+    fn receiver_ids() {
+        let mut a: Sender<u32> = Sender::new();
+        let mut b: Sender<u32> = Sender::new();
+        let (mut ra, mut rb) = (a.mk_receiver(), b.mk_receiver());
+        let ra2 = a.mk_receiver();
+        assert!(ra.id() != rb.id() && ra.id() != ra2.id() && rb.id() != ra2.id());
+        assert!(format!("{:?}", ra).contains(&format!("id: {}", ra.id())));
+
+        // Whoever is handling both receivers can tell which one's sender went away
+        ::std::mem::drop(a);
+        let orphaned: Vec<usize> = [&mut ra, &mut rb].iter_mut()
+            .filter(|r| r.inner.state() == Ok(WorkerState::Orphaned))
+            .map(|r| { assert_eq!(r.recv(), Err(RecvError::Orphaned)); r.id() })
+            .collect();
+        assert_eq!(orphaned, vec![ra.id()]);
+        ::std::mem::drop(b);
+    }
+
+    #[test]
+    fn try_clone() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut clone = sender.try_clone();
+        // Receivers created through either handle are visible to both
+        let threads: Vec<_> = (0..2).map(|_| {
+            let mut receiver = sender.mk_receiver();
+            thread::spawn(move || *receiver.recv().unwrap())
+        }).collect();
+        sleep_ms(10);
+
+        assert!(clone.enqueue(Box::new(1)).is_none());
+        assert!(clone.enqueue(Box::new(2)).is_none());
+        clone.wake_all();
+        let mut xs: Vec<u32> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        xs.sort();
+        assert_eq!(xs, vec![1, 2]);
+
+        // Dropping one handle doesn't orphan the receivers
+        let mut receiver = clone.mk_receiver();
+        ::std::mem::drop(sender);
+        let th = thread::spawn(move || *receiver.recv().unwrap());
+        sleep_ms(10);
+        assert!(clone.enqueue(Box::new(3)).is_none());
+        clone.wake_all();
+        assert_eq!(th.join().unwrap(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "testing")]
+    fn slow_receivers_get_less_work() {
+        let mut sender: Sender<()> = Sender::new();
+        let threads: Vec<_> = (0..3).map(|i| {
+            let delay = Duration::from_millis(if i == 0 { 30 } else { 0 });
+            let mut receiver = ThrottledReceiver::new(sender.mk_receiver(), move || delay);
+            thread::spawn(move || {
+                while receiver.recv().is_ok() {}
+                receiver.processed()
+            })
+        }).collect();
+        sleep_ms(10);
+
+        let mut sent = 0;
+        for _ in 0..30 {
+            for _ in 0..3 {
+                if sender.enqueue(Box::new(())).is_none() { sent += 1; }
+            }
+            sender.wake_all();
+            sleep_ms(2);
+        }
+        while sender.processed_counts().iter().sum::<u64>() < sent { thread::yield_now(); }
+        ::std::mem::drop(sender);
+        let totals: Vec<u64> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert!(totals[0] < totals[1].min(totals[2]), "{:?}", totals);
+    }
+
+    #[test]
+    fn burst_map() {
+        let squares = super::burst_map((0..100).collect(), 4, |x: u64| x * x);
+        assert_eq!(squares, (0..100).map(|x| x * x).collect::<Vec<_>>());
+        assert!(super::burst_map(vec![], 2, |x: u64| x).is_empty());
+    }
+
+    #[test]
+    fn recv_with_maintenance() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let maint = Arc::new(AtomicUsize::new(0));
+        let maint2 = maint.clone();
+        let th = thread::spawn(move || {
+            let mut got = vec![];
+            while let Ok(x) = r.recv_with_maintenance(Duration::from_millis(2), || { maint2.fetch_add(1, Ordering::SeqCst); }) {
+                got.push((*x, Instant::now()));
+            }
+            got
+        });
+        while maint.load(Ordering::SeqCst) < 3 { sleep_ms(1); }
+        let mut sent = vec![];
+        for i in 0..3 {
+            let mut x = Box::new(i);
+            while let Some(y) = sender.enqueue(x) { x = y; thread::yield_now(); }
+            sent.push(Instant::now());
+            sender.wake_all();
+            sleep_ms(10);
+        }
+        drop(sender);
+        let got = th.join().unwrap();
+        assert_eq!(got.iter().map(|x| x.0).collect::<Vec<_>>(), vec![0, 1, 2]);
+        for (&(_, at), sent) in got.iter().zip(sent) {
+            assert!(at - sent < Duration::from_millis(500));
+        }
+        assert!(maint.load(Ordering::SeqCst) > 3);
+    }
+
+    #[test]
+    fn burst_for() {
+        let count = AtomicUsize::new(0);
+        super::burst_for(0..1000, 4, |_| { count.fetch_add(1, Ordering::SeqCst); });
+        assert_eq!(count.load(Ordering::SeqCst), 1000);
+        let seen: Vec<AtomicBool> = (0..10).map(|_| AtomicBool::new(false)).collect();
+        super::burst_for(3..10, 3, |i| assert!(!seen[i].swap(true, Ordering::SeqCst)));
+        let seen: Vec<bool> = seen.iter().map(|b| b.load(Ordering::SeqCst)).collect();
+        assert_eq!(seen, (0..10).map(|i| i >= 3).collect::<Vec<_>>());
+        super::burst_for(5..5, 2, |_| panic!("empty range"));
+    }
+
+    #[test]
+    fn pool_lifecycle() {
+        let mut pool: Pool<u32> = Pool::new();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut workers = vec![];
+        let threads: Vec<_> = (0..3).map(|_| {
+            let (id, mut r) = pool.add_receiver();
+            workers.push(r.inner.clone());
+            let tx = tx.clone();
+            thread::spawn(move || {
+                while let Ok(x) = r.recv() { tx.send((id, *x)).unwrap(); }
+                id
+            })
+        }).collect();
+        let ids = pool.receiver_ids();
+        assert_eq!(ids.len(), 3);
+        while workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+
+        // Removing a receiver orphans it, and the rest carry on
+        assert!(pool.remove_receiver(ids[1]).is_none());
+        assert_eq!(pool.receiver_ids(), vec![ids[0], ids[2]]);
+        assert_eq!(pool.sender().workers.len(), 2);
+        assert!(pool.remove_receiver(ids[1]).is_none());
+        assert_eq!(pool.len(), 2);
+
+        let unplaced = pool.dispatch((0..3).map(Box::new).collect());
+        assert_eq!(unplaced.into_iter().map(|x| *x).collect::<Vec<_>>(), vec![2]);
+        let mut got: Vec<_> = (0..2).map(|_| rx.recv().unwrap()).collect();
+        got.sort();
+        let got_ids: Vec<_> = got.iter().map(|&(id, _)| id).collect();
+        assert_eq!(got_ids, vec![ids[0], ids[2]]);
+
+        // A removed receiver's pending payload comes back
+        while workers.iter().any(|w| w.state() == Ok(WorkerState::Running)) { sleep_ms(1); }
+        pool.sender().set_next_worker(1);
+        assert!(pool.sender().enqueue(Box::new(9)).is_none());
+        assert_eq!(pool.remove_receiver(ids[2]).map(|x| *x), Some(9));
+        assert_eq!(pool.receiver_ids(), vec![ids[0]]);
+
+        pool.shutdown();
+        let exited: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(exited, ids);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn processed_counts() {
+        let mut sender: Sender<()> = Sender::new();
+        let threads: Vec<_> = (0..3).map(|_| {
+            let mut receiver = sender.mk_receiver();
+            thread::spawn(move || {
+                while receiver.recv().is_ok() {}
+                receiver.processed()
+            })
+        }).collect();
+        sleep_ms(10);
+
+        let mut sent = 0;
+        for _ in 0..20 {
+            for _ in 0..3 {
+                if sender.enqueue(Box::new(())).is_none() { sent += 1; }
+            }
+            sender.wake_all();
+            sleep_ms(2);
+        }
+        let mut counts = sender.processed_counts();
+        while counts.iter().sum::<u64>() < sent {
+            thread::yield_now();
+            counts = sender.processed_counts();
+        }
+        ::std::mem::drop(sender);
+        let totals: Vec<u64> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        assert_eq!(counts, totals);
+        assert_eq!(totals.iter().sum::<u64>(), sent);
+        let (min, max) = (*totals.iter().min().unwrap(), *totals.iter().max().unwrap());
+        assert!(max - min <= 5, "{:?}", totals);
+    }
+
+    #[test]
+    fn yield_strategies() {
+        let strategies = [
+            YieldStrategy::None,
+            YieldStrategy::Yield,
+            YieldStrategy::Sleep(Duration::from_micros(100)),
+        ];
+        for &strategy in strategies.iter() {
+            let mut sender: Sender<u32> = Sender::new();
+            let threads: Vec<_> = (0..3).map(|_| {
+                let mut receiver = sender.mk_receiver();
+                receiver.set_yield_strategy(strategy);
+                thread::spawn(move || {
+                    let mut xs = vec![];
+                    while let Ok(x) = receiver.recv() { xs.push(*x); }
+                    xs
+                })
+            }).collect();
+            sleep_ms(10);
+
+            let mut sent = vec![];
+            for i in 0..10 {
+                // Only one receiver gets each message, so the others are woken spuriously
+                if sender.enqueue(Box::new(i)).is_none() { sent.push(i); }
+                sender.wake_all();
+                sleep_ms(2);
+            }
+            while sender.processed_counts().iter().sum::<u64>() < sent.len() as u64 {
+                thread::yield_now();
+            }
+            ::std::mem::drop(sender);
+            let mut received: Vec<u32> = threads.into_iter().flat_map(|t| t.join().unwrap()).collect();
+            received.sort();
+            assert_eq!(received, sent, "{:?}", strategy);
+        }
+    }
+
+    #[cfg(feature = "epoll")]
+    #[test]
+    fn epoll_wakes_once_per_write() {
+        // With poll, a non-zero initial value would wake the receiver over and over
+        let config = SenderConfig { initial: 1, ..SenderConfig::default() };
+        let mut sender: Sender<u32> = Sender::with_config(config);
+        let mut receiver = sender.mk_receiver();
+        let th = thread::spawn(move || {
+            let x = receiver.recv().unwrap();
+            (*x, receiver.spurious_wakeups())
+        });
+        sleep_ms(20);
+        assert!(sender.enqueue(Box::new(7)).is_none());
+        sender.wake_all();
+        let (x, spurious) = th.join().unwrap();
+        assert_eq!(x, 7);
+        assert!(spurious <= 1, "spurious = {}", spurious);
+    }
+
+    #[test]
+    fn sender_config() {
+        let config = SenderConfig { initial: 2, cloexec: true, nonblock: true, ..SenderConfig::default() };
+        let sender: Sender<u32> = Sender::with_config(config);
+        let fd_flags = unsafe { libc::fcntl(sender.eventfd.0, libc::F_GETFD) };
+        assert!(fd_flags & libc::FD_CLOEXEC != 0);
+        let fl_flags = unsafe { libc::fcntl(sender.eventfd.0, libc::F_GETFL) };
+        assert!(fl_flags & libc::O_NONBLOCK != 0);
+
+        // Semaphore mode: each read takes one from the initial value
+        let mut buf = [0; 8];
+        for _ in 0..2 {
+            sys_read(sender.eventfd.0, &mut buf).unwrap();
+            assert_eq!(NativeEndian::read_u64(&buf), 1);
+        }
+        assert!(sys_read(sender.eventfd.0, &mut buf).is_err());
+
+        let default: Sender<u32> = Sender::new();
+        let fd_flags = unsafe { libc::fcntl(default.eventfd.0, libc::F_GETFD) };
+        assert!(fd_flags & libc::FD_CLOEXEC == 0);
+    }
+
+    #[test]
+    fn slot_races() {
+        // Every payload is dropped exactly once, however the puts and takes interleave
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Counted(u64);
+        impl Drop for Counted {
+            fn drop(&mut self) { DROPS.fetch_add(1, Ordering::SeqCst); }
+        }
+        const M: u64 = 2000;
+        let w: Arc<Worker<Counted>> = Arc::new(Worker::new());
+        let putters: Vec<_> = (0..2).map(|_| {
+            let w = w.clone();
+            thread::spawn(move || {
+                for i in 0..M {
+                    let mut x = Box::into_raw(Box::new(Counted(i)));
+                    while let Err(y) = w.slot_put(x) { x = y; thread::yield_now(); }
+                }
+            })
+        }).collect();
+        let done = Arc::new(AtomicBool::new(false));
+        let takers: Vec<_> = (0..2).map(|_| {
+            let (w, done) = (w.clone(), done.clone());
+            thread::spawn(move || {
+                let (mut n, mut sum) = (0, 0);
+                while !done.load(Ordering::SeqCst) {
+                    match unsafe { w.slot_take_box() } {
+                        Some(x) => { n += 1; sum += x.0; }
+                        None => thread::yield_now(),
+                    }
+                }
+                (n, sum)
+            })
+        }).collect();
+        for th in putters { th.join().unwrap(); }
+        done.store(true, Ordering::SeqCst);
+        let (mut n, mut sum) = (0, 0);
+        for th in takers {
+            let x = th.join().unwrap();
+            n += x.0;
+            sum += x.1;
+        }
+        if let Some(x) = unsafe { w.slot_take_box() } { n += 1; sum += x.0; }
+        assert_eq!((n, sum), (2 * M, M * (M - 1)));
+        assert_eq!(DROPS.load(Ordering::SeqCst) as u64, 2 * M);
+
+        // Payloads left in a slot are dropped along with the worker, boxed or inline
+        let mut sender: Sender<Counted> = Sender::new();
+        let rs: Vec<_> = (0..2).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        assert!(sender.enqueue(Box::new(Counted(0))).is_none());
+        assert!(sender.enqueue_value(Counted(1)).is_none());
+        assert!(rs.iter().all(has_work));
+        drop(sender);
+        drop(rs);
+        assert_eq!(DROPS.load(Ordering::SeqCst) as u64, 2 * M + 2);
+
+        // Taking payloads back races with the receivers taking them, since a receiver which
+        // has been woken for someone else's payload may notice its own early
+        let mut sender: Sender<Counted> = Sender::new();
+        let receivers: Vec<_> = (0..2).map(|_| {
+            let mut r = sender.mk_receiver();
+            thread::spawn(move || {
+                let (mut n, mut sum) = (0, 0);
+                while let Ok(x) = r.recv() { n += 1; sum += x.0; }
+                (n, sum)
+            })
+        }).collect();
+        // An extra wakeup in the eventfd keeps both receivers spinning, looking for work
+        sender.eventfd.write(1).unwrap();
+        let (mut n, mut sum) = (0, 0);
+        for i in 0..M {
+            match sender.enqueue_with_token(Box::new(Counted(i))) {
+                Ok(token) => {
+                    // Sometimes give the receivers a chance to get there first
+                    if i % 2 == 0 { thread::yield_now(); }
+                    match i % 3 {
+                        0 => sender.wake_all(),
+                        1 => if let Some(x) = sender.cancel(token) { n += 1; sum += x.0; },
+                        _ => for x in sender.drain_unwoken() { n += 1; sum += x.0; },
+                    }
+                }
+                Err(x) => { n += 1; sum += x.0; thread::yield_now(); }
+            }
+        }
+        sender.wake_all();
+        sender.wait_for_ready(2, None);
+        drop(sender);
+        for th in receivers {
+            let x = th.join().unwrap();
+            n += x.0;
+            sum += x.1;
+        }
+        assert_eq!((n, sum), (M, M * (M - 1) / 2));
+        assert_eq!(DROPS.load(Ordering::SeqCst) as u64, 3 * M + 2);
+    }
+
+    #[test]
+    fn latency_breakdown() {
+        let mut sender: Sender<u32> = Sender::new();
+        sender.set_timestamps(true);
+        let mut r = sender.mk_receiver();
+        let th = thread::spawn(move || {
+            let x = *r.recv().unwrap();
+            assert_eq!(r.latency(), None);
+            sleep_ms(50);  // a slow job
+            r.done();
+            (x, r.latency().unwrap())
+        });
+        while sender.workers[0].state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        assert!(sender.enqueue(Box::new(3)).is_none());
+        sender.wake_all();
+        let (x, latency) = th.join().unwrap();
+        assert_eq!(x, 3);
+        assert!(latency.process >= Duration::from_millis(50), "{:?}", latency);
+        assert!(latency.queue < latency.process, "{:?}", latency);
+
+        // Without timestamps there's nothing to report
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        park_all(&sender);
+        assert!(sender.enqueue(Box::new(4)).is_none());
+        sender.wake_all();
+        r.recv().unwrap();
+        r.done();
+        assert_eq!(r.latency(), None);
+    }
+
+    #[cfg(feature = "hdr")]
+    #[test]
+    fn latency_histogram_percentiles() {
+        use hdrhistogram::serialization::{Deserializer, Serializer, V2Serializer};
+        let mut hist = hdrhistogram::Histogram::new(3).unwrap();
+        // 1us, 2us, ..., 10ms, split between queueing and processing
+        for i in 1..=10_000u64 {
+            let latency = LatencyBreakdown { queue: Duration::from_nanos(i * 300), process: Duration::from_nanos(i * 700) };
+            record_latency(&mut hist, latency);
+        }
+        assert_eq!(hist.len(), 10_000);
+        let p99 = hist.value_at_quantile(0.99) as f64;
+        assert!((p99 - 9_900_000.0).abs() / 9_900_000.0 < 0.001, "{}", p99);
+
+        // It survives being sent to another host
+        let mut buf = vec![];
+        V2Serializer::new().serialize(&hist, &mut buf).unwrap();
+        let other: hdrhistogram::Histogram<u64> = Deserializer::new().deserialize(&mut &buf[..]).unwrap();
+        assert_eq!(other, hist);
+    }
+
+    #[cfg(feature = "hdr")]
+    #[test]
+    fn latency_histogram_is_shared() {
+        let mut sender: Sender<u32> = Sender::new();
+        sender.set_timestamps(true);
+        let mut r = sender.mk_receiver();
+        let handle = sender.try_clone();
+        let th = thread::spawn(move || {
+            for _ in 0..3 {
+                r.recv().unwrap();
+                sleep_ms(5);
+                r.done();
+            }
+        });
+        for i in 0..3 {
+            while sender.workers[0].state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+            assert!(sender.enqueue(Box::new(i)).is_none());
+            sender.wake_all();
+        }
+        th.join().unwrap();
+        let hist = handle.latency_histogram();
+        assert_eq!(hist.len(), 3);
+        assert!(hist.min() >= 5_000_000, "{}", hist.min());
+    }
+
+    #[test]
+    fn rotate_receivers() {
+        const N: u32 = 200;
+        fn worker(mut r: Receiver<u32>, version: u32) -> thread::JoinHandle<Vec<(u32, u32)>> {
+            thread::spawn(move || {
+                let mut got = vec![];
+                while let Ok(x) = r.recv() {
+                    got.push((version, *x));
+                    thread::yield_now();
+                }
+                got
+            })
+        }
+        let mut sender: Sender<u32> = Sender::new();
+        let mut ths: Vec<_> = (0..2).map(|_| worker(sender.mk_receiver(), 1)).collect();
+        let mut todo: VecDeque<Box<u32>> = (0..N).map(Box::new).collect();
+        let mut rotated = false;
+        while let Some(x) = todo.pop_front() {
+            if *x == N / 2 && !rotated {
+                rotated = true;
+                todo.push_front(x);
+                let taken_back = sender.rotate_receivers(2, |r| ths.push(worker(r, 2)));
+                for x in taken_back.into_iter().rev() { todo.push_front(x); }
+                continue;
+            }
+            if let Some(x) = sender.enqueue(x) {
+                todo.push_front(x);
+                sender.wake_all();
+                thread::yield_now();
+            }
+        }
+        sender.wake_all();
+        assert_eq!(sender.wait_for_ready(2, None), 2);
+        assert_eq!(sender.workers.len(), 2);
+        drop(sender);
+        let got: Vec<(u32, u32)> = ths.into_iter().flat_map(|th| th.join().unwrap()).collect();
+        let mut xs: Vec<u32> = got.iter().map(|x| x.1).collect();
+        xs.sort();
+        assert_eq!(xs, (0..N).collect::<Vec<_>>());
+        // The new receivers got the second half, and nothing went to the old ones after that
+        let last_old = got.iter().filter(|x| x.0 == 1).map(|x| x.1).max().unwrap();
+        let first_new = got.iter().filter(|x| x.0 == 2).map(|x| x.1).min().unwrap();
+        assert!(last_old < N / 2 && first_new <= N / 2, "{} {}", last_old, first_new);
+    }
+
+    #[test]
+    fn compact() {
+        let mut sender: Sender<u32> = Sender::new();
+        let mut rs: Vec<Option<Receiver<u32>>> = (0..5).map(|_| Some(sender.mk_receiver())).collect();
+        park_all(&sender);
+        // One of the receivers to be dropped has been given a payload, and woken
+        sender.set_next_worker(2);
+        assert!(sender.enqueue(Box::new(7)).is_none());
+        sender.wake_all();
+        for &i in &[1, 2, 4] { rs[i] = None; }
+        assert_eq!(sender.eventfd_balance(), 1);
+        assert_eq!(sender.compact().into_iter().map(|x| *x).collect::<Vec<_>>(), vec![7]);
+        assert_eq!(sender.workers.len(), 2);
+        assert_eq!(sender.registry.workers.lock().unwrap().len(), 2);
+        assert_eq!(sender.eventfd_balance(), 0);
+        assert!(sender.compact().is_empty());
+
+        let ths: Vec<_> = rs.into_iter().flatten().map(|mut r| thread::spawn(move || *r.recv().unwrap())).collect();
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        for i in 0..2 { assert!(sender.enqueue(Box::new(i)).is_none()); }
+        assert!(sender.enqueue(Box::new(2)).is_some());
+        sender.wake_all();
+        let mut got: Vec<u32> = ths.into_iter().map(|th| th.join().unwrap()).collect();
+        got.sort();
+        assert_eq!(got, vec![0, 1]);
+    }
+
+    #[test]
+    fn begin_burst() {
+        let mut sender: Sender<u32> = Sender::new();
+        let done = Arc::new(AtomicUsize::new(0));
+        let ths: Vec<_> = (0..5).map(|_| {
+            let mut r = sender.mk_receiver();
+            let done = done.clone();
+            thread::spawn(move || {
+                while r.recv().is_ok() {
+                    sleep_ms(20);
+                    done.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        }).collect();
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        let token = sender.begin_burst();
+        for i in 0..5 { assert!(sender.enqueue(Box::new(i)).is_none()); }
+        sender.end_burst();
+        sender.wake_all();
+        assert_eq!(token.remaining(), 5);
+        assert!(!token.wait_timeout(Duration::from_millis(1)));
+        token.wait();
+        assert_eq!(done.load(Ordering::SeqCst), 5);
+
+        // Payloads which are taken back count as finished
+        while sender.workers.iter().any(|w| w.state() != Ok(WorkerState::Waiting)) { sleep_ms(1); }
+        let token = sender.begin_burst();
+        assert!(sender.enqueue(Box::new(5)).is_none());
+        assert_eq!(token.remaining(), 1);
+        assert_eq!(sender.drain_unwoken().len(), 1);
+        assert!(token.wait_timeout(Duration::from_secs(5)));
+        drop(sender);
+        for th in ths { th.join().unwrap(); }
+    }
+
+    #[test]
+    fn report() {
+        const N: u64 = 20;
+        let mut sender: Sender<u32> = Sender::new();
+        let mut busy = sender.mk_receiver();
+        let stuck = sender.mk_receiver();
+        stuck.inner.set_state(WorkerState::Waiting);
+        let th = thread::spawn(move || {
+            while busy.recv().is_ok() {}
+        });
+        // The first payload goes to the receiver which never takes it, and the rest to the other
+        sender.set_next_worker(1);
+        for i in 0..N as u32 {
+            let mut x = Box::new(i);
+            while let Some(y) = sender.enqueue(x) { x = y; thread::yield_now(); }
+            sender.wake_all();
+        }
+        while sender.report().receivers[0].processed < N - 1 { thread::yield_now(); }
+        let report = sender.report();
+        assert_eq!(report.receivers.len(), 2);
+        let (busy, stuck) = (&report.receivers[0], &report.receivers[1]);
+        assert_eq!((busy.index, busy.processed, busy.outstanding), (0, N - 1, false));
+        assert_eq!(stuck.index, 1);
+        assert_eq!(stuck.state, Ok(WorkerState::Pending));
+        assert_eq!((stuck.processed, stuck.spurious_wakeups, stuck.outstanding), (0, 0, true));
+        drop(sender);
+        th.join().unwrap();
+    }
+
+    #[test]
+    fn worker_is_cache_aligned() {
+        fn check<T>() {
+            assert_eq!(mem::align_of::<Worker<T>>(), 64);
+            assert_eq!(mem::size_of::<Worker<T>>() % 64, 0);
+        }
+        check::<u8>();
+        check::<[u64; 16]>();
+        let workers: Vec<Arc<Worker<u32>>> = (0..4).map(|_| Arc::new(Worker::new())).collect();
+        for w in &workers {
+            assert_eq!(&**w as *const Worker<u32> as usize % 64, 0);
+        }
+    }
+
+    #[test]
+    fn reap_orphaned() {
+        let mut sender: Sender<u32> = Sender::new();
+        let rs: Vec<_> = (0..3).map(|_| sender.mk_receiver()).collect();
+        park_all(&sender);
+        for i in 0..3 { assert!(sender.enqueue_value(i).is_none()); }
+        sender.wake_all();
+        assert!(sender.reap_orphaned().is_empty());
+        // Orphan two of them from outside, as if by another process
+        rs[0].inner.set_state(WorkerState::Orphaned);
+        rs[2].inner.set_state(WorkerState::Orphaned);
+        let reaped: Vec<u32> = sender.reap_orphaned().into_iter().map(|x| *x).collect();
+        assert_eq!(reaped, vec![0, 2]);
+        assert!(sender.reap_orphaned().is_empty());
+        assert!(has_work(&rs[1]));
+    }
+
+    #[test]
+    fn recv_interruptible() {
+        let extra = sys_eventfd(0, 0).unwrap();
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let th = thread::spawn(move || {
+            loop {
+                match r.recv_interruptible(extra).unwrap() {
+                    Interrupted::Work(x) => return tx.send(Some(*x)).unwrap(),
+                    Interrupted::External => {
+                        let mut buf = [0; 8];
+                        sys_read(extra, &mut buf).unwrap();
+                        tx.send(None).unwrap();
+                    }
+                }
+            }
+        });
+        while sender.workers[0].state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        assert!(sender.enqueue(Box::new(5)).is_none());
+        let mut buf = [0; 8];
+        NativeEndian::write_u64(&mut buf, 1);
+        sys_write(extra, &buf).unwrap();
+        assert_eq!(rx.recv().unwrap(), None);
+        // The payload is still waiting to be woken
+        assert_eq!(sender.workers[0].state(), Ok(WorkerState::Pending));
+        assert_eq!(sender.eventfd_balance(), 0);
+        sender.wake_all();
+        assert_eq!(rx.recv().unwrap(), Some(5));
+        th.join().unwrap();
+        assert_eq!(sender.eventfd_balance(), 0);
+        unsafe { libc::close(extra); }
+    }
+
+    #[test]
+    fn interrupted_by_signal() {
+        use std::os::unix::thread::JoinHandleExt;
+        extern "C" fn ignore(_: libc::c_int) {}
+        // No SA_RESTART, so that blocking calls fail with EINTR
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = ignore as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            assert_eq!(libc::sigaction(libc::SIGUSR1, &action, ptr::null_mut()), 0);
+        }
+        let mut sender: Sender<u32> = Sender::new();
+        let mut r = sender.mk_receiver();
+        let th = thread::spawn(move || {
+            let mut got = vec![];
+            while let Ok(x) = r.recv() { got.push(*x); }
+            got
+        });
+        for i in 0..3 {
+            while sender.workers[0].state() != Ok(WorkerState::Waiting) {
+                assert!(!th.is_finished(), "the receiver gave up");
+                sleep_ms(1);
+            }
+            for _ in 0..3 {
+                assert_eq!(unsafe { libc::pthread_kill(th.as_pthread_t(), libc::SIGUSR1) }, 0);
+                sleep_ms(2);
+            }
+            assert!(sender.enqueue(Box::new(i)).is_none());
+            sender.wake_all();
+        }
+        while sender.workers[0].state() != Ok(WorkerState::Waiting) { sleep_ms(1); }
+        drop(sender);
+        assert_eq!(th.join().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn from_raw_fd() {
+        let fd = sys_eventfd(0, libc::EFD_SEMAPHORE).unwrap();
+        let worker: Arc<Worker<u32>> = Arc::new(Worker::new());
+        let mut r = unsafe { Receiver::from_raw_fd(fd, worker.clone()) };
+        let th = thread::spawn(move || *r.recv().unwrap());
+        // Play the part of the sender by hand
+        while worker.cas_state(WorkerState::Waiting, WorkerState::Pending) != Ok(WorkerState::Waiting) {
+            sleep_ms(1);
+        }
+        worker.slot.store(Box::into_raw(Box::new(5)), Ordering::SeqCst);
+        let mut buf = [0; 8];
+        NativeEndian::write_u64(&mut buf, 1);
+        sys_write(fd, &buf).unwrap();
+        assert_eq!(th.join().unwrap(), 5);
+    }
+
+    #[test]
+    fn set_realtime_priority() {
+        let mut sender: Sender<u32> = Sender::new();
+        let r = sender.mk_receiver();
+        thread::spawn(move || {
+            match r.set_realtime_priority(1) {
+                // We need CAP_SYS_NICE for this, so there's nothing to check without it
+                Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+                res => res.unwrap(),
+            }
+            let mut policy = 0;
+            let mut param = libc::sched_param { sched_priority: 0 };
+            assert_eq!(unsafe { libc::pthread_getschedparam(libc::pthread_self(), &mut policy, &mut param) }, 0);
+            assert_eq!((policy, param.sched_priority), (libc::SCHED_FIFO, 1));
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn drain_eventfd() {
+        for &semaphore in &[true, false] {
+            let mut sender: Sender<u32> = Sender::with_config(SenderConfig { semaphore, ..SenderConfig::default() });
+            assert_eq!(sender.drain_eventfd().unwrap(), 0);
+            // Wakeups with nobody around to take them
+            sender.workers_to_unblock = 3;
+            sender.wake_all();
+            assert_eq!(sender.drain_eventfd().unwrap(), 3);
+            assert_eq!(sender.drain_eventfd().unwrap(), 0);
+        }
+    }
 }